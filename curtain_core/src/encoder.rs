@@ -0,0 +1,91 @@
+//! Quadrature decoding for two-channel hall encoders.
+
+/// Count change for each transition, indexed by the previous and the new
+/// channel levels as `a << 1 | b`.
+const STEPS: [Option<i8>; 16] = [
+    Some(0),
+    Some(1),
+    Some(-1),
+    None,
+    Some(-1),
+    Some(0),
+    None,
+    Some(1),
+    Some(1),
+    None,
+    Some(0),
+    Some(-1),
+    None,
+    Some(-1),
+    Some(1),
+    Some(0),
+];
+
+/// Turns the levels of channels A and B into counts, four per cycle, positive
+/// while B leads.
+pub struct Quadrature {
+    levels: u8,
+}
+
+impl Quadrature {
+    pub fn new(a: bool, b: bool) -> Self {
+        Self {
+            levels: levels(a, b),
+        }
+    }
+
+    /// Takes the levels after an edge; returns the count change, or `None`
+    /// when both channels changed at once and a pulse was missed.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<i8> {
+        let levels = levels(a, b);
+        let step = STEPS[usize::from(self.levels << 2 | levels)];
+        self.levels = levels;
+        step
+    }
+}
+
+fn levels(a: bool, b: bool) -> u8 {
+    u8::from(a) << 1 | u8::from(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One cycle with B leading, as `(a, b)` levels after each edge.
+    const B_LEADS: [(bool, bool); 4] = [(false, true), (true, true), (true, false), (false, false)];
+
+    #[test]
+    fn counts_up_while_b_leads() {
+        let mut decoder = Quadrature::new(false, false);
+        for _ in 0..2 {
+            for (a, b) in B_LEADS {
+                assert_eq!(decoder.update(a, b), Some(1));
+            }
+        }
+    }
+
+    #[test]
+    fn counts_down_while_a_leads() {
+        let mut decoder = Quadrature::new(false, false);
+        for (a, b) in B_LEADS.iter().rev().skip(1).chain(&B_LEADS[3..]) {
+            assert_eq!(decoder.update(*a, *b), Some(-1));
+        }
+    }
+
+    #[test]
+    fn ignores_repeated_levels() {
+        let mut decoder = Quadrature::new(true, false);
+        assert_eq!(decoder.update(true, false), Some(0));
+    }
+
+    #[test]
+    fn reports_illegal_jumps() {
+        let mut decoder = Quadrature::new(false, false);
+        assert_eq!(decoder.update(true, true), None);
+        // Decoding resumes from the new levels.
+        assert_eq!(decoder.update(true, false), Some(1));
+        assert_eq!(decoder.update(false, true), None);
+        assert_eq!(decoder.update(true, true), Some(1));
+    }
+}
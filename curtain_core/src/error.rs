@@ -0,0 +1,19 @@
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    NotCalibrated,
+    CalibrationFailed,
+    /// Both limit switches are active at once, which points at a wiring fault.
+    LimitSwitchFault,
+    /// The motor stalled or drew overcurrent; see [`crate::stall`].
+    Stalled,
+}
+
+// region:    --- Error Boilerplate
+impl core::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        write!(fmt, "{self:?}")
+    }
+}
+// endregion: --- Error Boilerplate
@@ -0,0 +1,145 @@
+//! Position feedback, such as the potentiometer built into many linear
+//! actuators read through an ADC, or the counts of a hall encoder.
+
+/// Smallest difference between the readings at both ends that calibration
+/// accepts; less means the sensor is not connected or does not move.
+pub const MIN_SPAN: i32 = 64;
+
+/// A sensor whose reading follows the actuator's position.
+pub trait PositionSensor {
+    /// Waits for the next reading; while the motor runs the controller calls
+    /// this in a loop, so it sets the polling rate.
+    fn sample(&mut self) -> impl Future<Output = i32>;
+
+    /// Whether readings count from wherever the sensor started, as an
+    /// encoder's do, rather than being absolute. An incremental sensor's range
+    /// is only known after a calibration, and is re-referenced whenever a
+    /// limit switch trips.
+    fn is_incremental(&self) -> bool {
+        false
+    }
+}
+
+/// Stands in for the sensor of a controller without feedback; there are no
+/// values of it to sample.
+pub enum NoFeedback {}
+
+impl PositionSensor for NoFeedback {
+    fn sample(&mut self) -> impl Future<Output = i32> {
+        core::future::pending()
+    }
+}
+
+/// Readings at the closed and the open end, in either order; measured by
+/// calibration, or known for a given device. For an encoder, a hundredth of
+/// the span is its counts per percent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedbackRange {
+    pub closed: i32,
+    pub open: i32,
+}
+
+impl FeedbackRange {
+    pub fn is_plausible(&self) -> bool {
+        self.closed.abs_diff(self.open) >= MIN_SPAN.unsigned_abs()
+    }
+
+    /// Position (0 closed, 100 open) for `reading`, clamped to the range.
+    pub fn position(&self, reading: i32) -> u8 {
+        let span = i64::from(self.open) - i64::from(self.closed);
+        if span == 0 {
+            return 0;
+        }
+        let position = (i64::from(reading) - i64::from(self.closed)) * 100 / span;
+        position.clamp(0, 100) as u8
+    }
+
+    /// The same range, moved so that `reading` is the one at `position`.
+    pub fn shifted_to(&self, position: u8, reading: i32) -> Self {
+        let span = i64::from(self.open) - i64::from(self.closed);
+        let expected = i64::from(self.closed) + span * i64::from(position) / 100;
+        let offset = (i64::from(reading) - expected) as i32;
+        Self {
+            closed: self.closed.saturating_add(offset),
+            open: self.open.saturating_add(offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A potentiometer read through a 12-bit ADC.
+    const POT: FeedbackRange = FeedbackRange {
+        closed: 300,
+        open: 3300,
+    };
+    /// The same, wired the other way round.
+    const INVERTED: FeedbackRange = FeedbackRange {
+        closed: 3300,
+        open: 300,
+    };
+
+    #[test]
+    fn rejects_implausible_ranges() {
+        assert!(POT.is_plausible());
+        assert!(INVERTED.is_plausible());
+        let disconnected = FeedbackRange {
+            closed: 4095,
+            open: 4095,
+        };
+        assert!(!disconnected.is_plausible());
+        let narrow = FeedbackRange {
+            closed: 1000,
+            open: 1000 + MIN_SPAN - 1,
+        };
+        assert!(!narrow.is_plausible());
+        let extreme = FeedbackRange {
+            closed: i32::MIN,
+            open: i32::MAX,
+        };
+        assert!(extreme.is_plausible());
+    }
+
+    #[test]
+    fn maps_readings_to_percent() {
+        assert_eq!(POT.position(300), 0);
+        assert_eq!(POT.position(1500), 40);
+        assert_eq!(POT.position(3300), 100);
+        assert_eq!(INVERTED.position(3300), 0);
+        assert_eq!(INVERTED.position(2100), 40);
+        assert_eq!(INVERTED.position(300), 100);
+    }
+
+    #[test]
+    fn clamps_readings_beyond_the_ends() {
+        assert_eq!(POT.position(0), 0);
+        assert_eq!(POT.position(4095), 100);
+        assert_eq!(INVERTED.position(4095), 0);
+        assert_eq!(INVERTED.position(0), 100);
+        assert_eq!(POT.position(i32::MIN), 0);
+        assert_eq!(POT.position(i32::MAX), 100);
+        let empty = FeedbackRange { closed: 5, open: 5 };
+        assert_eq!(empty.position(5), 0);
+    }
+
+    #[test]
+    fn shifts_to_a_reference() {
+        // Encoder counts that drifted by 50 by the time the end stop trips.
+        let counts = FeedbackRange {
+            closed: 0,
+            open: 1000,
+        };
+        let shifted = counts.shifted_to(0, 50);
+        assert_eq!(
+            shifted,
+            FeedbackRange {
+                closed: 50,
+                open: 1050
+            }
+        );
+        assert_eq!(shifted.position(550), 50);
+        assert_eq!(INVERTED.shifted_to(40, 2000).position(2000), 40);
+    }
+}
@@ -0,0 +1,249 @@
+//! Debouncing and press detection for digital inputs, driven by edge
+//! notifications and timestamps so it runs the same on the device and the
+//! host.
+//!
+//! The caller reports every edge with [`Debouncer::on_edge`], and calls
+//! [`Debouncer::poll`] with the current level after each edge and whenever
+//! [`Debouncer::deadline`] passes. A level counts once the input has been quiet
+//! for the debounce time.
+
+/// How an input is debounced and what counts as pressed.
+#[derive(Debug, Clone, Copy)]
+pub struct InputSettings {
+    pub debounce_ms: u64,
+    /// Whether a high level means pressed (or, for an end stop, reached).
+    pub active_high: bool,
+    /// Hold time after which [`InputEvent::LongPress`] is reported, if any.
+    pub long_press_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// The debounced level changed.
+    Edge {
+        high: bool,
+    },
+    Pressed,
+    Released {
+        held_ms: u64,
+    },
+    /// Still pressed after [`InputSettings::long_press_ms`]; reported once
+    /// per press.
+    LongPress,
+}
+
+pub struct Debouncer {
+    settings: InputSettings,
+    high: bool,
+    /// Time of the last edge while the input has not been quiet long enough.
+    last_edge: Option<u64>,
+    pressed_at: Option<u64>,
+    long_press_reported: bool,
+}
+
+impl Debouncer {
+    pub fn new(settings: InputSettings, high: bool, now_ms: u64) -> Self {
+        let pressed = high == settings.active_high;
+        Self {
+            settings,
+            high,
+            last_edge: None,
+            pressed_at: pressed.then_some(now_ms),
+            long_press_reported: false,
+        }
+    }
+
+    /// The debounced level.
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.high == self.settings.active_high
+    }
+
+    pub fn on_edge(&mut self, now_ms: u64) {
+        self.last_edge = Some(now_ms);
+    }
+
+    /// Time at which [`Debouncer::poll`] has to be called even without an edge.
+    pub fn deadline(&self) -> Option<u64> {
+        let settled = self.last_edge.map(|t| t + self.settings.debounce_ms);
+        let long_press = match (self.pressed_at, self.settings.long_press_ms) {
+            (Some(t), Some(long)) if !self.long_press_reported => Some(t + long),
+            _ => None,
+        };
+        match (settled, long_press) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Takes the input's current raw level into account; returns the events
+    /// that result, in order.
+    pub fn poll(&mut self, high: bool, now_ms: u64) -> [Option<InputEvent>; 3] {
+        let mut events = [None, None, None];
+
+        if let Some(pressed_at) = self.pressed_at
+            && let Some(long) = self.settings.long_press_ms
+            && !self.long_press_reported
+            && now_ms >= pressed_at + long
+        {
+            self.long_press_reported = true;
+            events[0] = Some(InputEvent::LongPress);
+        }
+
+        let settled = self
+            .last_edge
+            .is_some_and(|t| now_ms >= t + self.settings.debounce_ms);
+        if !settled {
+            return events;
+        }
+        self.last_edge = None;
+        if high == self.high {
+            return events;
+        }
+
+        self.high = high;
+        let press = if self.is_pressed() {
+            self.pressed_at = Some(now_ms);
+            self.long_press_reported = false;
+            InputEvent::Pressed
+        } else {
+            let held_ms = self.pressed_at.take().map_or(0, |t| now_ms - t);
+            InputEvent::Released { held_ms }
+        };
+        events[1] = Some(InputEvent::Edge { high });
+        events[2] = Some(press);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An active-low button with a 20 ms debounce and a 1 s long press.
+    const BUTTON: InputSettings = InputSettings {
+        debounce_ms: 20,
+        active_high: false,
+        long_press_ms: Some(1000),
+    };
+    const NOTHING: [Option<InputEvent>; 3] = [None, None, None];
+    const PRESSED: [Option<InputEvent>; 3] = [
+        None,
+        Some(InputEvent::Edge { high: false }),
+        Some(InputEvent::Pressed),
+    ];
+
+    fn released(held_ms: u64) -> [Option<InputEvent>; 3] {
+        [
+            None,
+            Some(InputEvent::Edge { high: true }),
+            Some(InputEvent::Released { held_ms }),
+        ]
+    }
+
+    /// Reports an edge to `level` at `now_ms` and polls right away.
+    fn edge(input: &mut Debouncer, level: bool, now_ms: u64) -> [Option<InputEvent>; 3] {
+        input.on_edge(now_ms);
+        input.poll(level, now_ms)
+    }
+
+    #[test]
+    fn ignores_bounces_within_the_window() {
+        let mut input = Debouncer::new(BUTTON, true, 0);
+        assert_eq!(edge(&mut input, false, 100), NOTHING);
+        assert_eq!(edge(&mut input, true, 105), NOTHING);
+        assert_eq!(edge(&mut input, false, 110), NOTHING);
+        assert_eq!(edge(&mut input, true, 115), NOTHING);
+        assert_eq!(input.deadline(), Some(135));
+        // Back where it started once quiet: no change to report.
+        assert_eq!(input.poll(true, 135), NOTHING);
+        assert!(input.is_high());
+        assert_eq!(input.deadline(), None);
+    }
+
+    #[test]
+    fn settles_on_the_last_level() {
+        let mut input = Debouncer::new(BUTTON, true, 0);
+        assert_eq!(edge(&mut input, false, 100), NOTHING);
+        assert_eq!(edge(&mut input, true, 105), NOTHING);
+        assert_eq!(edge(&mut input, false, 110), NOTHING);
+        assert_eq!(input.poll(false, 129), NOTHING);
+        assert_eq!(input.poll(false, 130), PRESSED);
+        assert!(input.is_pressed());
+    }
+
+    #[test]
+    fn reports_hold_time_on_release() {
+        let mut input = Debouncer::new(BUTTON, true, 0);
+        edge(&mut input, false, 100);
+        assert_eq!(input.poll(false, 120), PRESSED);
+        edge(&mut input, true, 400);
+        assert_eq!(input.poll(true, 420), released(300));
+        assert!(!input.is_pressed());
+    }
+
+    #[test]
+    fn reports_long_press_once() {
+        let mut input = Debouncer::new(BUTTON, true, 0);
+        edge(&mut input, false, 100);
+        assert_eq!(input.poll(false, 120), PRESSED);
+        assert_eq!(input.deadline(), Some(1120));
+        assert_eq!(input.poll(false, 1119), NOTHING);
+        assert_eq!(
+            input.poll(false, 1120),
+            [Some(InputEvent::LongPress), None, None]
+        );
+        assert_eq!(input.deadline(), None);
+        assert_eq!(input.poll(false, 5000), NOTHING);
+        edge(&mut input, true, 6000);
+        assert_eq!(input.poll(true, 6020), released(5900));
+
+        // The next press gets its own.
+        edge(&mut input, false, 7000);
+        assert_eq!(input.poll(false, 7020), PRESSED);
+        assert_eq!(input.deadline(), Some(8020));
+    }
+
+    #[test]
+    fn counts_a_button_held_at_boot_as_pressed() {
+        let mut input = Debouncer::new(BUTTON, false, 50);
+        assert!(input.is_pressed());
+        assert_eq!(input.deadline(), Some(1050));
+        assert_eq!(
+            input.poll(false, 1050),
+            [Some(InputEvent::LongPress), None, None]
+        );
+        edge(&mut input, true, 2000);
+        assert_eq!(input.poll(true, 2020), released(1970));
+    }
+
+    #[test]
+    fn passes_edges_through_without_debounce() {
+        let settings = InputSettings {
+            debounce_ms: 0,
+            active_high: true,
+            long_press_ms: None,
+        };
+        let mut input = Debouncer::new(settings, false, 0);
+        assert_eq!(
+            edge(&mut input, true, 10),
+            [
+                None,
+                Some(InputEvent::Edge { high: true }),
+                Some(InputEvent::Pressed)
+            ]
+        );
+        assert_eq!(
+            edge(&mut input, false, 11),
+            [
+                None,
+                Some(InputEvent::Edge { high: false }),
+                Some(InputEvent::Released { held_ms: 1 })
+            ]
+        );
+        assert_eq!(input.deadline(), None);
+    }
+}
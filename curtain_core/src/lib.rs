@@ -0,0 +1,15 @@
+//! Hardware-independent motor and calibration logic.
+//!
+//! Pins are taken as `embedded-hal` traits so the firmware can plug in esp-hal
+//! GPIOs while the logic itself stays free of any chip support crate.
+#![no_std]
+
+pub mod encoder;
+pub mod error;
+pub mod feedback;
+pub mod input;
+pub mod lineat_motor;
+pub mod ramp;
+pub mod stall;
+
+pub use error::{Error, Result};
@@ -0,0 +1,1257 @@
+use embassy_futures::select::{Either3, select3};
+use embedded_hal::digital::InputPin;
+use embedded_hal::pwm::SetDutyCycle;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::error::{Error, Result};
+use crate::feedback::{FeedbackRange, NoFeedback, PositionSensor};
+use crate::ramp::{NoRamps, RampProfile, RampSettings};
+use crate::stall::StallAction;
+
+/// Extra run time, in percent of a full stroke, for moves that must reach an
+/// end of travel whatever the estimated position.
+const OVERRUN_PERCENT: u64 = 20;
+
+/// Positions run from closed (0), where the end stop is, to open (100), where
+/// the optional open-end switch is.
+const END_STOP_POSITION: u8 = 0;
+const MAX_POSITION: u8 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Opening,
+    Closing,
+}
+
+impl Direction {
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::Opening => Self::Closing,
+            Self::Closing => Self::Opening,
+        }
+    }
+}
+
+/// A move in progress: the motor runs in `direction` until the caller's wait
+/// returns, which should be after `duration_ms` unless the limit switch in
+/// that direction (the end stop when closing, the open-end switch when
+/// opening) trips, the motor stalls or a stop is requested first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub direction: Direction,
+    pub duration_ms: u64,
+}
+
+/// Why a [`Move`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveEnd {
+    Completed,
+    /// The limit switch in the direction of travel tripped.
+    EndStop {
+        elapsed_ms: u64,
+    },
+    Stopped {
+        elapsed_ms: u64,
+    },
+    /// The caller detected a stall or overcurrent; see [`crate::stall`].
+    Stalled {
+        elapsed_ms: u64,
+    },
+}
+
+/// Time-based position control: the position is estimated from how long the
+/// motor ran, and re-referenced whenever a limit switch trips. With a
+/// position sensor (see [`LinearMotorController::with_feedback`]) positions
+/// are read instead, and moves stop once the sensor reaches the target.
+///
+/// The end stop at the closed end is required; a second switch at the open
+/// end is optional and, when present, lets calibration measure the travel
+/// time instead of relying on the configured one.
+///
+/// Moves are timed by the caller through an async `wait` so the switches can
+/// be watched while the motor runs, and the outputs cut as soon as one trips.
+/// The H-bridge inputs are PWM outputs; with ramps (see
+/// [`LinearMotorController::with_ramps`]) moves start and end slowly,
+/// otherwise the motor runs at full speed.
+pub struct LinearMotorController<E, O, A, B, S = NoFeedback, D = NoRamps> {
+    linear_motor: Motor<E, O, A, B>,
+    state: Option<u8>,
+    full_travel_ms: u64,
+    end_stop_trip: Option<u8>,
+    stall_action: StallAction,
+    feedback: Option<S>,
+    /// Only ever set with a sensor.
+    range: Option<FeedbackRange>,
+    ramps: Option<(RampSettings, D)>,
+}
+
+impl<E: InputPin, O: InputPin, A: SetDutyCycle, B: SetDutyCycle> LinearMotorController<E, O, A, B> {
+    /// `full_travel_ms` is the time the actuator takes from closed to open;
+    /// with an `open_end` switch, calibration measures it.
+    pub fn new(end_point: E, open_end: Option<O>, a: A, b: B, full_travel_ms: u64) -> Self {
+        Self {
+            linear_motor: Motor::new(end_point, open_end, a, b),
+            state: None,
+            full_travel_ms,
+            end_stop_trip: None,
+            stall_action: StallAction::Stop,
+            feedback: None,
+            range: None,
+            ramps: None,
+        }
+    }
+}
+
+impl<E: InputPin, O: InputPin, A: SetDutyCycle, B: SetDutyCycle, D>
+    LinearMotorController<E, O, A, B, NoFeedback, D>
+{
+    /// Reads positions from `sensor` rather than estimating them. `range` is
+    /// the sensor's readings at both ends if known for this device, otherwise
+    /// calibration measures it; an incremental sensor always needs the
+    /// calibration. Without a sensor the controller stays time-based.
+    pub fn with_feedback<S: PositionSensor>(
+        self,
+        sensor: Option<S>,
+        range: Option<FeedbackRange>,
+    ) -> LinearMotorController<E, O, A, B, S, D> {
+        LinearMotorController {
+            linear_motor: self.linear_motor,
+            state: self.state,
+            full_travel_ms: self.full_travel_ms,
+            end_stop_trip: self.end_stop_trip,
+            stall_action: self.stall_action,
+            range: range.filter(|_| sensor.as_ref().is_some_and(|s| !s.is_incremental())),
+            feedback: sensor,
+            ramps: self.ramps,
+        }
+    }
+}
+
+impl<E: InputPin, O: InputPin, A: SetDutyCycle, B: SetDutyCycle, S>
+    LinearMotorController<E, O, A, B, S, NoRamps>
+{
+    /// Ramps the speed of every move as `settings` say, timing the duty
+    /// cycle updates with `delay`. Without settings the motor runs at full
+    /// speed.
+    pub fn with_ramps<D: DelayNs>(
+        self,
+        settings: Option<RampSettings>,
+        delay: D,
+    ) -> LinearMotorController<E, O, A, B, S, D> {
+        LinearMotorController {
+            linear_motor: self.linear_motor,
+            state: self.state,
+            full_travel_ms: self.full_travel_ms,
+            end_stop_trip: self.end_stop_trip,
+            stall_action: self.stall_action,
+            feedback: self.feedback,
+            range: self.range,
+            ramps: settings.map(|settings| (settings, delay)),
+        }
+    }
+}
+
+impl<E: InputPin, O: InputPin, A: SetDutyCycle, B: SetDutyCycle, S: PositionSensor, D: DelayNs>
+    LinearMotorController<E, O, A, B, S, D>
+{
+    /// What to do when a move stalls outside calibration; stops by default.
+    /// A stall during calibration always leaves the controller uncalibrated.
+    pub fn with_stall_action(mut self, action: StallAction) -> Self {
+        self.stall_action = action;
+        self
+    }
+
+    pub async fn set_state(
+        &mut self,
+        new_state: u8,
+        mut wait: impl AsyncFnMut(Move) -> MoveEnd,
+    ) -> Result<()> {
+        let Some(current) = self.state else {
+            return Err(Error::NotCalibrated);
+        };
+        self.check_limits()?;
+        let current = self.read_position().await.unwrap_or(current);
+        let new_state = new_state.min(MAX_POSITION);
+        let (direction, distance) = if new_state >= current {
+            (Direction::Opening, new_state - current)
+        } else {
+            (Direction::Closing, current - new_state)
+        };
+        let duration_ms = u64::from(distance) * self.full_travel_ms / u64::from(MAX_POSITION);
+
+        self.state = None;
+        let end = self
+            .run(
+                Move {
+                    direction,
+                    duration_ms,
+                },
+                Some(current),
+                Some(new_state),
+                &mut wait,
+            )
+            .await;
+        self.check_limits()?;
+        let reached = self.settle(current, new_state, direction, end).await;
+        self.state = Some(reached);
+        if let MoveEnd::Stalled { .. } = end {
+            return self.after_stall(reached, direction, &mut wait).await;
+        }
+        Ok(())
+    }
+
+    pub fn get_state(&self) -> Option<u8> {
+        self.state
+    }
+
+    /// With a known feedback range, takes the position from the sensor, e.g.
+    /// at start-up or after the actuator was moved by hand.
+    pub async fn sync_position(&mut self) {
+        if let Some(position) = self.read_position().await {
+            self.state = Some(position);
+        }
+    }
+
+    /// The sensor's readings at both ends, as given or measured.
+    pub fn feedback_range(&self) -> Option<FeedbackRange> {
+        self.range
+    }
+
+    /// Estimated position at which a limit switch last tripped during a move;
+    /// anything but that switch's end (0 closed, 100 open) is drift of the
+    /// time-based estimate.
+    pub fn end_stop_trip(&self) -> Option<u8> {
+        self.end_stop_trip
+    }
+
+    /// Time a full stroke takes, as configured or measured by calibration.
+    pub fn full_travel_ms(&self) -> u64 {
+        self.full_travel_ms
+    }
+
+    /// Without an open-end switch: opens fully, running up against the
+    /// mechanical end (a stall there counts as having arrived), then closes
+    /// until the end stop trips. With one: closes until the end stop trips, then opens until
+    /// the open-end switch trips, taking the time that took as the travel time.
+    /// With a position sensor, its readings at both ends become the feedback
+    /// range.
+    pub async fn calibrate(&mut self, mut wait: impl AsyncFnMut(Move) -> MoveEnd) -> Result<()> {
+        self.forget_position();
+        self.check_limits()?;
+        if self.linear_motor.has_open_end() {
+            return self.calibrate_between_switches(&mut wait).await;
+        }
+        let duration_ms = self.overrun_ms();
+        let opened = self
+            .run(
+                Move {
+                    direction: Direction::Opening,
+                    duration_ms,
+                },
+                None,
+                None,
+                &mut wait,
+            )
+            .await;
+        // The overrun runs into the mechanical end on purpose; the motor
+        // stalling there is the end reached, not the travel blocked.
+        if !matches!(opened, MoveEnd::Completed | MoveEnd::Stalled { .. }) {
+            return Err(Error::CalibrationFailed);
+        }
+        let open_reading = self.sample().await;
+        let closed = self
+            .run(
+                Move {
+                    direction: Direction::Closing,
+                    duration_ms,
+                },
+                Some(MAX_POSITION),
+                None,
+                &mut wait,
+            )
+            .await;
+        self.check_limits()?;
+        check_calibration_move(closed)?;
+        if !matches!(closed, MoveEnd::EndStop { .. })
+            && !self.linear_motor.limit_reached(Direction::Closing)
+        {
+            return Err(Error::CalibrationFailed);
+        }
+        let closed_reading = self.sample().await;
+        self.set_range(closed_reading, open_reading)?;
+        self.state = Some(END_STOP_POSITION);
+        Ok(())
+    }
+
+    async fn calibrate_between_switches(
+        &mut self,
+        wait: &mut impl AsyncFnMut(Move) -> MoveEnd,
+    ) -> Result<()> {
+        let duration_ms = self.overrun_ms();
+        let closed = self
+            .run(
+                Move {
+                    direction: Direction::Closing,
+                    duration_ms,
+                },
+                None,
+                None,
+                wait,
+            )
+            .await;
+        self.check_limits()?;
+        check_calibration_move(closed)?;
+        if !matches!(closed, MoveEnd::EndStop { .. }) {
+            return Err(Error::CalibrationFailed);
+        }
+        let closed_reading = self.sample().await;
+        let opened = self
+            .run(
+                Move {
+                    direction: Direction::Opening,
+                    duration_ms,
+                },
+                Some(END_STOP_POSITION),
+                None,
+                wait,
+            )
+            .await;
+        self.check_limits()?;
+        check_calibration_move(opened)?;
+        let MoveEnd::EndStop { elapsed_ms } = opened else {
+            return Err(Error::CalibrationFailed);
+        };
+        if elapsed_ms == 0 {
+            return Err(Error::CalibrationFailed);
+        }
+        let open_reading = self.sample().await;
+        self.set_range(closed_reading, open_reading)?;
+        self.full_travel_ms = elapsed_ms;
+        self.state = Some(MAX_POSITION);
+        Ok(())
+    }
+
+    /// Takes the readings at both ends as the feedback range; without a
+    /// sensor there are none.
+    fn set_range(&mut self, closed: Option<i32>, open: Option<i32>) -> Result<()> {
+        let (Some(closed), Some(open)) = (closed, open) else {
+            return Ok(());
+        };
+        let range = FeedbackRange { closed, open };
+        if !range.is_plausible() {
+            return Err(Error::CalibrationFailed);
+        }
+        self.range = Some(range);
+        Ok(())
+    }
+
+    async fn sample(&mut self) -> Option<i32> {
+        match self.feedback.as_mut() {
+            Some(sensor) => Some(sensor.sample().await),
+            None => None,
+        }
+    }
+
+    async fn read_position(&mut self) -> Option<u8> {
+        let range = self.range?;
+        let reading = self.sample().await?;
+        Some(range.position(reading))
+    }
+
+    /// Forgets the position along with the feedback range, so it takes a
+    /// calibration to move again; e.g. once the sensor can no longer be
+    /// trusted.
+    pub fn forget_position(&mut self) {
+        self.state = None;
+        self.range = None;
+    }
+
+    /// Where a move from `start` toward `target` left the actuator.
+    fn landed(&mut self, start: u8, target: u8, direction: Direction, end: MoveEnd) -> u8 {
+        match end {
+            MoveEnd::Completed => target,
+            MoveEnd::EndStop { elapsed_ms } => {
+                self.end_stop_trip = Some(self.position_after(start, direction, elapsed_ms));
+                match direction {
+                    Direction::Opening => MAX_POSITION,
+                    Direction::Closing => END_STOP_POSITION,
+                }
+            }
+            MoveEnd::Stopped { elapsed_ms } | MoveEnd::Stalled { elapsed_ms } => {
+                self.position_after(start, direction, elapsed_ms)
+            }
+        }
+    }
+
+    /// Where a move left the actuator: read from the sensor if there is one,
+    /// estimated otherwise.
+    async fn settle(&mut self, start: u8, target: u8, direction: Direction, end: MoveEnd) -> u8 {
+        let estimate = self.landed(start, target, direction, end);
+        // A limit switch is a better reference than counts that may have
+        // missed a pulse.
+        if let MoveEnd::EndStop { .. } = end
+            && self.feedback.as_ref().is_some_and(|s| s.is_incremental())
+            && let Some(range) = self.range
+            && let Some(reading) = self.sample().await
+        {
+            self.range = Some(range.shifted_to(estimate, reading));
+        }
+        self.read_position().await.unwrap_or(estimate)
+    }
+
+    /// Carries out the stall action after a move in `direction` stalled at
+    /// `position`.
+    async fn after_stall(
+        &mut self,
+        position: u8,
+        direction: Direction,
+        wait: &mut impl AsyncFnMut(Move) -> MoveEnd,
+    ) -> Result<()> {
+        match self.stall_action {
+            StallAction::Stop => {}
+            StallAction::Reverse { duration_ms } => {
+                let direction = direction.reversed();
+                let motion = Move {
+                    direction,
+                    duration_ms,
+                };
+                let end = self.run(motion, Some(position), None, wait).await;
+                self.check_limits()?;
+                let target = self.position_after(position, direction, duration_ms);
+                self.state = Some(self.settle(position, target, direction, end).await);
+            }
+            StallAction::Fault => self.forget_position(),
+        }
+        Err(Error::Stalled)
+    }
+
+    /// Both switches active at once can only be a wiring fault; the position
+    /// is then unknown.
+    fn check_limits(&mut self) -> Result<()> {
+        if self.linear_motor.limit_reached(Direction::Closing)
+            && self.linear_motor.limit_reached(Direction::Opening)
+        {
+            self.forget_position();
+            return Err(Error::LimitSwitchFault);
+        }
+        Ok(())
+    }
+
+    /// Run time for moves that must reach an end of travel.
+    fn overrun_ms(&self) -> u64 {
+        self.full_travel_ms * (100 + OVERRUN_PERCENT) / 100
+    }
+
+    /// Runs `motion`, cutting power however it ends. With feedback, the move
+    /// also completes once the sensor reports `target` reached, and only then;
+    /// the motion's duration just bounds it. With ramps, the speed follows a
+    /// [`RampProfile`] from `start`, and the elapsed time of the returned end
+    /// is the distance covered in run time at full speed. Without a `start`,
+    /// as in calibration, an end of travel may be anywhere ahead, so the whole
+    /// move runs at the approach speed.
+    async fn run(
+        &mut self,
+        motion: Move,
+        start: Option<u8>,
+        target: Option<u8>,
+        wait: &mut impl AsyncFnMut(Move) -> MoveEnd,
+    ) -> MoveEnd {
+        if self.linear_motor.limit_reached(motion.direction) {
+            return MoveEnd::EndStop { elapsed_ms: 0 };
+        }
+        if motion.duration_ms == 0 {
+            return MoveEnd::Completed;
+        }
+        let closed_loop = target.is_some() && self.range.is_some();
+        let mut bound = Move {
+            direction: motion.direction,
+            duration_ms: if closed_loop {
+                motion.duration_ms.max(self.overrun_ms())
+            } else {
+                motion.duration_ms
+            },
+        };
+        let to_end = match (start, motion.direction) {
+            (Some(start), Direction::Opening) => MAX_POSITION - start.min(MAX_POSITION),
+            (Some(start), Direction::Closing) => start,
+            (None, _) => 0,
+        };
+        let to_end_ms = u64::from(to_end) * self.full_travel_ms / u64::from(MAX_POSITION);
+        let mut profile = self
+            .ramps
+            .as_ref()
+            .map(|(settings, _)| RampProfile::new(*settings, motion.duration_ms, Some(to_end_ms)));
+        if let Some(profile) = &profile {
+            bound.duration_ms = profile.time_bound(bound.duration_ms);
+        }
+
+        let Self {
+            linear_motor,
+            feedback,
+            range,
+            ramps,
+            ..
+        } = self;
+        linear_motor.drive(motion.direction, profile.map_or(100, |p| p.duty()));
+        let reached = async {
+            let (Some(target), Some(range), Some(sensor)) = (target, *range, feedback.as_mut())
+            else {
+                return core::future::pending().await;
+            };
+            loop {
+                let position = range.position(sensor.sample().await);
+                let reached = match motion.direction {
+                    Direction::Opening => position >= target,
+                    Direction::Closing => position <= target,
+                };
+                if reached {
+                    break;
+                }
+            }
+        };
+        let ramped = async {
+            let (Some(profile), Some((settings, delay))) = (profile.as_mut(), ramps.as_mut())
+            else {
+                return core::future::pending().await;
+            };
+            loop {
+                delay.delay_ms(settings.step_ms).await;
+                profile.advance(settings.step_ms);
+                // The sensor has the final say on where the target is.
+                if profile.arrived() && !closed_loop {
+                    break;
+                }
+                linear_motor.drive(motion.direction, profile.duty());
+            }
+        };
+        let end = match select3(wait(bound), reached, ramped).await {
+            Either3::First(end) => end,
+            Either3::Second(()) | Either3::Third(()) => MoveEnd::Completed,
+        };
+        self.linear_motor.stop();
+        match profile {
+            Some(profile) => end.with_elapsed(profile.travelled_ms()),
+            None => end,
+        }
+    }
+
+    /// Estimated position after running from `start` for `elapsed_ms`.
+    fn position_after(&self, start: u8, direction: Direction, elapsed_ms: u64) -> u8 {
+        let travelled = elapsed_ms * u64::from(MAX_POSITION) / self.full_travel_ms.max(1);
+        let travelled = u8::try_from(travelled).unwrap_or(MAX_POSITION);
+        match direction {
+            Direction::Opening => start.saturating_add(travelled).min(MAX_POSITION),
+            Direction::Closing => start.saturating_sub(travelled),
+        }
+    }
+
+    /// Cuts power to the actuator; the known position is kept.
+    pub fn stop(&mut self) {
+        self.linear_motor.stop();
+    }
+}
+
+impl MoveEnd {
+    /// The same end, `elapsed_ms` into the move.
+    fn with_elapsed(self, elapsed_ms: u64) -> Self {
+        match self {
+            Self::Completed => Self::Completed,
+            Self::EndStop { .. } => Self::EndStop { elapsed_ms },
+            Self::Stopped { .. } => Self::Stopped { elapsed_ms },
+            Self::Stalled { .. } => Self::Stalled { elapsed_ms },
+        }
+    }
+}
+
+/// A stall while calibrating toward a limit switch means the travel is
+/// blocked; the controller is left uncalibrated.
+fn check_calibration_move(end: MoveEnd) -> Result<()> {
+    match end {
+        MoveEnd::Stalled { .. } => Err(Error::Stalled),
+        _ => Ok(()),
+    }
+}
+
+struct Motor<E, O, A, B> {
+    end_point: E,
+    open_end: Option<O>,
+    a: A,
+    b: B,
+}
+
+impl<E: InputPin, O: InputPin, A: SetDutyCycle, B: SetDutyCycle> Motor<E, O, A, B> {
+    pub fn new(end_point: E, open_end: Option<O>, a: A, b: B) -> Self {
+        let mut motor = Self {
+            end_point,
+            open_end,
+            a,
+            b,
+        };
+        motor.stop();
+        motor
+    }
+
+    fn has_open_end(&self) -> bool {
+        self.open_end.is_some()
+    }
+
+    /// Whether the limit switch in `direction` is active; without an open-end
+    /// switch, the open end is never reported reached.
+    fn limit_reached(&mut self, direction: Direction) -> bool {
+        match direction {
+            Direction::Closing => matches!(self.end_point.is_high(), Ok(true)),
+            Direction::Opening => self
+                .open_end
+                .as_mut()
+                .is_some_and(|open_end| matches!(open_end.is_high(), Ok(true))),
+        }
+    }
+
+    /// Drives the motor in `direction` at `duty` percent.
+    fn drive(&mut self, direction: Direction, duty: u8) {
+        // Never drive both inputs, even for an instant.
+        match direction {
+            Direction::Opening => {
+                let _ = self.b.set_duty_cycle_fully_off();
+                let _ = self.a.set_duty_cycle_percent(duty);
+            }
+            Direction::Closing => {
+                let _ = self.a.set_duty_cycle_fully_off();
+                let _ = self.b.set_duty_cycle_percent(duty);
+            }
+        }
+    }
+
+    /// Drives both H-bridge inputs low so the actuator coasts.
+    pub fn stop(&mut self) {
+        let _ = self.a.set_duty_cycle_fully_off();
+        let _ = self.b.set_duty_cycle_fully_off();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    use embassy_futures::{block_on, yield_now};
+
+    use super::*;
+
+    const FULL_TRAVEL_MS: u64 = 10_000;
+    /// Simulated time per poll of [`Rig::timed`].
+    const TICK_MS: u64 = 10;
+    const RAMPS: RampSettings = RampSettings {
+        accel_ms: 500,
+        decel_ms: 500,
+        approach_duty: 40,
+        approach_ms: 1_000,
+        step_ms: 20,
+    };
+
+    /// A limit switch whose level the test sets.
+    struct Switch<'a>(&'a Cell<bool>);
+
+    impl embedded_hal::digital::ErrorType for Switch<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for Switch<'_> {
+        fn is_high(&mut self) -> core::result::Result<bool, Infallible> {
+            Ok(self.0.get())
+        }
+
+        fn is_low(&mut self) -> core::result::Result<bool, Infallible> {
+            Ok(!self.0.get())
+        }
+    }
+
+    /// A PWM output that keeps its duty cycle, in percent, for the test.
+    struct Pwm<'a>(&'a Cell<u16>);
+
+    impl embedded_hal::pwm::ErrorType for Pwm<'_> {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for Pwm<'_> {
+        fn max_duty_cycle(&self) -> u16 {
+            100
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> core::result::Result<(), Infallible> {
+            self.0.set(duty);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Rig {
+        end_stop: Cell<bool>,
+        open_end: Cell<bool>,
+        a: Cell<u16>,
+        b: Cell<u16>,
+        with_open_end: Cell<bool>,
+        /// Where the actuator really is, in run time from the closed end.
+        position_ms: Cell<u64>,
+        /// Hundredths of a millisecond of travel that timed moves carry over.
+        fraction: Cell<u64>,
+        /// How much slower than configured the actuator really is, in percent.
+        slowdown_percent: Cell<u64>,
+        /// Counts an [`Encoder`] gained that the actuator did not move.
+        slip: Cell<i32>,
+        moves: RefCell<Vec<Move>>,
+        /// Simulated time of [`Rig::timed`] moves.
+        now_ms: Cell<u64>,
+        /// Highest duty cycle of each timed move, and the one it ended at.
+        duties: RefCell<Vec<(u16, u16)>>,
+        /// Run time after which a timed move is stopped.
+        stop_after_ms: Cell<Option<u64>>,
+    }
+
+    /// Readings of a potentiometer on the rig's actuator through a 12-bit ADC.
+    const POT: FeedbackRange = FeedbackRange {
+        closed: 300,
+        open: 3300,
+    };
+
+    /// The potentiometer of [`POT`], read once per poll.
+    struct Pot<'a>(&'a Rig);
+
+    impl PositionSensor for Pot<'_> {
+        async fn sample(&mut self) -> i32 {
+            yield_now().await;
+            let span = (POT.open - POT.closed) as u64;
+            POT.closed + (self.0.position_ms.get() * span / FULL_TRAVEL_MS) as i32
+        }
+    }
+
+    /// An encoder on the rig's actuator with 1000 counts over the travel,
+    /// counting from wherever it started.
+    struct Encoder<'a>(&'a Rig);
+
+    impl PositionSensor for Encoder<'_> {
+        async fn sample(&mut self) -> i32 {
+            yield_now().await;
+            let counts = self.0.position_ms.get() * 1000 / FULL_TRAVEL_MS;
+            counts as i32 + self.0.slip.get() - 500
+        }
+
+        fn is_incremental(&self) -> bool {
+            true
+        }
+    }
+
+    /// A sensor stuck at one reading, e.g. disconnected.
+    struct Stuck;
+
+    impl PositionSensor for Stuck {
+        async fn sample(&mut self) -> i32 {
+            yield_now().await;
+            2048
+        }
+    }
+
+    /// The ramps' delay, on the rig's simulated time.
+    struct Clock<'a>(&'a Rig);
+
+    impl DelayNs for Clock<'_> {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.delay_ms(ns / 1_000_000).await;
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            let until = self.0.now_ms.get() + u64::from(ms);
+            while self.0.now_ms.get() < until {
+                yield_now().await;
+            }
+        }
+    }
+
+    type Controller<'a, S = NoFeedback, D = NoRamps> =
+        LinearMotorController<Switch<'a>, Switch<'a>, Pwm<'a>, Pwm<'a>, S, D>;
+
+    impl Rig {
+        fn controller(&self, with_open_end: bool) -> Controller<'_> {
+            self.with_open_end.set(with_open_end);
+            self.position_ms.set(FULL_TRAVEL_MS / 2);
+            LinearMotorController::new(
+                Switch(&self.end_stop),
+                with_open_end.then_some(Switch(&self.open_end)),
+                Pwm(&self.a),
+                Pwm(&self.b),
+                FULL_TRAVEL_MS,
+            )
+        }
+
+        /// Returns at once with `end(motion)`, after recording the move and
+        /// checking the H-bridge drives it.
+        fn wait(&self, mut end: impl FnMut(Move) -> MoveEnd) -> impl AsyncFnMut(Move) -> MoveEnd {
+            async move |motion| {
+                let driven = match motion.direction {
+                    Direction::Opening => (100, 0),
+                    Direction::Closing => (0, 100),
+                };
+                assert_eq!((self.a.get(), self.b.get()), driven);
+                self.moves.borrow_mut().push(motion);
+                end(motion)
+            }
+        }
+
+        /// A travel of `FULL_TRAVEL_MS`: the end stop ends a move toward it
+        /// early, as does the open-end switch if there is one; without it the
+        /// actuator runs up against its end. Anything else completes.
+        fn travel(&self) -> impl AsyncFnMut(Move) -> MoveEnd {
+            self.wait(move |motion| {
+                let position = self.position_ms.get();
+                self.end_stop.set(false);
+                self.open_end.set(false);
+                let (to_end, switch) = match motion.direction {
+                    Direction::Closing => (position, Some(&self.end_stop)),
+                    Direction::Opening => (
+                        FULL_TRAVEL_MS - position,
+                        self.with_open_end.get().then_some(&self.open_end),
+                    ),
+                };
+                let run_ms = motion.duration_ms.min(to_end);
+                self.position_ms.set(match motion.direction {
+                    Direction::Opening => position + run_ms,
+                    Direction::Closing => position - run_ms,
+                });
+                match switch {
+                    Some(switch) if motion.duration_ms >= to_end => {
+                        switch.set(true);
+                        MoveEnd::EndStop { elapsed_ms: to_end }
+                    }
+                    _ => MoveEnd::Completed,
+                }
+            })
+        }
+
+        /// Runs the actuator in simulated time, [`TICK_MS`] per poll, at the
+        /// duty cycle the controller drives it at, until the move's time is up
+        /// or a limit switch in its direction trips. Without an open-end
+        /// switch the actuator stops at its mechanical end.
+        fn timed(&self) -> impl AsyncFnMut(Move) -> MoveEnd {
+            async move |motion| {
+                self.moves.borrow_mut().push(motion);
+                self.end_stop.set(false);
+                self.open_end.set(false);
+                let started = self.now_ms.get();
+                self.duties.borrow_mut().push((0, 0));
+                loop {
+                    let duty = match motion.direction {
+                        Direction::Opening => self.a.get(),
+                        Direction::Closing => self.b.get(),
+                    };
+                    if let Some((peak, last)) = self.duties.borrow_mut().last_mut() {
+                        *peak = duty.max(*peak);
+                        *last = duty;
+                    }
+                    let speed = u64::from(duty) * (100 - self.slowdown_percent.get()) / 100;
+                    let step = self.fraction.get() + TICK_MS * speed;
+                    self.fraction.set(step % 100);
+                    let step = step / 100;
+                    let position = self.position_ms.get();
+                    let position = match motion.direction {
+                        Direction::Opening => (position + step).min(FULL_TRAVEL_MS),
+                        Direction::Closing => position.saturating_sub(step),
+                    };
+                    self.position_ms.set(position);
+                    self.now_ms.set(self.now_ms.get() + TICK_MS);
+                    let elapsed_ms = self.now_ms.get() - started;
+                    let switch = match motion.direction {
+                        Direction::Closing => (position == 0).then_some(&self.end_stop),
+                        Direction::Opening => (position == FULL_TRAVEL_MS
+                            && self.with_open_end.get())
+                        .then_some(&self.open_end),
+                    };
+                    let end = if let Some(switch) = switch {
+                        switch.set(true);
+                        Some(MoveEnd::EndStop { elapsed_ms })
+                    } else if self
+                        .stop_after_ms
+                        .get()
+                        .is_some_and(|after| elapsed_ms >= after)
+                    {
+                        Some(MoveEnd::Stopped { elapsed_ms })
+                    } else if elapsed_ms >= motion.duration_ms {
+                        Some(MoveEnd::Completed)
+                    } else {
+                        None
+                    };
+                    if let Some(end) = end {
+                        return end;
+                    }
+                    yield_now().await;
+                }
+            }
+        }
+
+        fn moves(&self) -> Vec<Move> {
+            self.moves.take()
+        }
+
+        /// Where the actuator really is, in percent.
+        fn position(&self) -> u64 {
+            self.position_ms.get() * 100 / FULL_TRAVEL_MS
+        }
+
+        fn idle(&self) -> bool {
+            self.a.get() == 0 && self.b.get() == 0
+        }
+    }
+
+    fn opening(duration_ms: u64) -> Move {
+        Move {
+            direction: Direction::Opening,
+            duration_ms,
+        }
+    }
+
+    fn closing(duration_ms: u64) -> Move {
+        Move {
+            direction: Direction::Closing,
+            duration_ms,
+        }
+    }
+
+    /// A controller with ramps, calibrated without an open-end switch, at 0.
+    fn ramped(rig: &Rig) -> Controller<'_, NoFeedback, Clock<'_>> {
+        let mut controller = rig.controller(false).with_ramps(Some(RAMPS), Clock(rig));
+        block_on(controller.calibrate(rig.timed())).unwrap();
+        rig.moves();
+        rig.duties.take();
+        controller
+    }
+
+    /// A controller calibrated without an open-end switch, at 0.
+    fn calibrated(rig: &Rig) -> Controller<'_> {
+        let mut controller = rig.controller(false);
+        block_on(controller.calibrate(rig.travel())).unwrap();
+        rig.moves();
+        controller
+    }
+
+    #[test]
+    fn refuses_to_move_uncalibrated() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false);
+        let result = block_on(controller.set_state(40, rig.wait(|_| unreachable!())));
+        assert!(matches!(result, Err(Error::NotCalibrated)));
+        assert!(rig.moves().is_empty());
+    }
+
+    #[test]
+    fn calibrates_against_the_end_stop() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false);
+        block_on(controller.calibrate(rig.travel())).unwrap();
+        assert_eq!(rig.moves(), [opening(12_000), closing(12_000)]);
+        assert_eq!(controller.get_state(), Some(0));
+        assert_eq!(controller.full_travel_ms(), FULL_TRAVEL_MS);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn fails_calibration_without_the_end_stop() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false);
+        let result = block_on(controller.calibrate(rig.wait(|_| MoveEnd::Completed)));
+        assert!(matches!(result, Err(Error::CalibrationFailed)));
+        assert_eq!(controller.get_state(), None);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn takes_a_stall_in_the_open_overrun_for_the_end() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false);
+        // The encoder or the current sense notices the actuator at its
+        // mechanical end before the overrun is over.
+        let wait = rig.wait(|motion| match motion.direction {
+            Direction::Opening => MoveEnd::Stalled { elapsed_ms: 5000 },
+            Direction::Closing => {
+                rig.end_stop.set(true);
+                MoveEnd::EndStop {
+                    elapsed_ms: FULL_TRAVEL_MS,
+                }
+            }
+        });
+        block_on(controller.calibrate(wait)).unwrap();
+        assert_eq!(rig.moves(), [opening(12_000), closing(12_000)]);
+        assert_eq!(controller.get_state(), Some(0));
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn fails_calibration_on_a_stall_toward_the_end_stop() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false);
+        let wait = rig.wait(|motion| match motion.direction {
+            Direction::Opening => MoveEnd::Completed,
+            Direction::Closing => MoveEnd::Stalled { elapsed_ms: 3000 },
+        });
+        let result = block_on(controller.calibrate(wait));
+        assert!(matches!(result, Err(Error::Stalled)));
+        assert_eq!(controller.get_state(), None);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn measures_the_travel_between_switches() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(true);
+        let mut travel = rig.wait(|motion| match motion.direction {
+            Direction::Closing => MoveEnd::EndStop { elapsed_ms: 3000 },
+            Direction::Opening => MoveEnd::EndStop { elapsed_ms: 8000 },
+        });
+        block_on(controller.calibrate(&mut travel)).unwrap();
+        assert_eq!(rig.moves(), [closing(12_000), opening(12_000)]);
+        assert_eq!(controller.full_travel_ms(), 8000);
+        assert_eq!(controller.get_state(), Some(100));
+    }
+
+    #[test]
+    fn times_moves_from_the_travel() {
+        let rig = Rig::default();
+        let mut controller = calibrated(&rig);
+        block_on(controller.set_state(40, rig.travel())).unwrap();
+        assert_eq!(controller.get_state(), Some(40));
+        block_on(controller.set_state(25, rig.travel())).unwrap();
+        assert_eq!(controller.get_state(), Some(25));
+        // Already there: nothing to run.
+        block_on(controller.set_state(25, rig.travel())).unwrap();
+        assert_eq!(rig.moves(), [opening(4000), closing(1500)]);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn estimates_the_position_of_a_stopped_move() {
+        let rig = Rig::default();
+        let mut controller = calibrated(&rig);
+        let result =
+            block_on(controller.set_state(80, rig.wait(|_| MoveEnd::Stopped { elapsed_ms: 3000 })));
+        assert!(result.is_ok());
+        assert_eq!(controller.get_state(), Some(30));
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn re_references_on_an_early_end_stop() {
+        let rig = Rig::default();
+        let mut controller = calibrated(&rig);
+        block_on(controller.set_state(50, rig.travel())).unwrap();
+        // The curtain drifted: the end stop trips at an estimated 10.
+        let tripped = rig.wait(|_| {
+            rig.end_stop.set(true);
+            MoveEnd::EndStop { elapsed_ms: 4000 }
+        });
+        block_on(controller.set_state(0, tripped)).unwrap();
+        assert_eq!(controller.get_state(), Some(0));
+        assert_eq!(controller.end_stop_trip(), Some(10));
+    }
+
+    #[test]
+    fn does_not_run_into_an_active_end_stop() {
+        let rig = Rig::default();
+        let mut controller = calibrated(&rig);
+        block_on(controller.set_state(50, rig.travel())).unwrap();
+        rig.moves();
+        rig.end_stop.set(true);
+        block_on(controller.set_state(20, rig.wait(|_| unreachable!()))).unwrap();
+        assert!(rig.moves().is_empty());
+        assert_eq!(controller.get_state(), Some(0));
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn faults_on_both_switches_active() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(true);
+        rig.end_stop.set(true);
+        rig.open_end.set(true);
+        let result = block_on(controller.calibrate(rig.wait(|_| unreachable!())));
+        assert!(matches!(result, Err(Error::LimitSwitchFault)));
+        assert_eq!(controller.get_state(), None);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn backs_off_after_a_stall() {
+        let rig = Rig::default();
+        let mut controller =
+            calibrated(&rig).with_stall_action(StallAction::Reverse { duration_ms: 500 });
+        block_on(controller.set_state(50, rig.travel())).unwrap();
+        rig.moves();
+        let mut stalled = false;
+        let wait = rig.wait(|_| {
+            if core::mem::replace(&mut stalled, true) {
+                MoveEnd::Completed
+            } else {
+                MoveEnd::Stalled { elapsed_ms: 2000 }
+            }
+        });
+        let result = block_on(controller.set_state(100, wait));
+        assert!(matches!(result, Err(Error::Stalled)));
+        assert_eq!(rig.moves(), [opening(5000), closing(500)]);
+        assert_eq!(controller.get_state(), Some(65));
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn forgets_the_position_on_a_stall_fault() {
+        let rig = Rig::default();
+        let mut controller = calibrated(&rig).with_stall_action(StallAction::Fault);
+        let result =
+            block_on(controller.set_state(50, rig.wait(|_| MoveEnd::Stalled { elapsed_ms: 1000 })));
+        assert!(matches!(result, Err(Error::Stalled)));
+        assert_eq!(controller.get_state(), None);
+    }
+
+    #[test]
+    fn calibrates_at_the_approach_speed() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false).with_ramps(Some(RAMPS), Clock(&rig));
+        block_on(controller.calibrate(rig.timed())).unwrap();
+        assert_eq!(controller.get_state(), Some(0));
+        assert_eq!(rig.position_ms.get(), 0);
+        // Out from an unknown position slowly; back from the open end at full
+        // speed, slowing down before the end stop.
+        assert_eq!(rig.duties.take(), [(40, 40), (100, 40)]);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn measures_the_travel_at_the_approach_speed() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(true).with_ramps(Some(RAMPS), Clock(&rig));
+        block_on(controller.calibrate(rig.timed())).unwrap();
+        assert_eq!(controller.get_state(), Some(100));
+        assert_eq!(rig.duties.take(), [(40, 40), (100, 40)]);
+        // The distance covered, not the longer time it took.
+        let measured = controller.full_travel_ms();
+        assert!(measured.abs_diff(FULL_TRAVEL_MS) <= 10, "{}", measured);
+    }
+
+    #[test]
+    fn ramps_moves_to_their_target() {
+        let rig = Rig::default();
+        let mut controller = ramped(&rig);
+        block_on(controller.set_state(60, rig.timed())).unwrap();
+        assert_eq!(controller.get_state(), Some(60));
+        // Within the last step at the approach speed.
+        assert!(rig.position_ms.get().abs_diff(6_000) <= 10);
+        // Starts and ends slowly; the bound leaves room for that.
+        assert_eq!(rig.duties.take(), [(100, 40)]);
+        let [motion] = rig.moves()[..] else {
+            panic!("one move");
+        };
+        assert!(motion.duration_ms > 6_000, "{:?}", motion);
+        block_on(controller.set_state(20, rig.timed())).unwrap();
+        assert_eq!(controller.get_state(), Some(20));
+        assert!(rig.position_ms.get().abs_diff(2_000) <= 20);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn estimates_a_stopped_ramp_by_the_distance_covered() {
+        let rig = Rig::default();
+        let mut controller = ramped(&rig);
+        rig.stop_after_ms.set(Some(3_000));
+        let result = block_on(controller.set_state(100, rig.timed()));
+        assert!(result.is_ok());
+        // Less than 3 s at full speed: the ramp started slowly.
+        let position = rig.position();
+        assert!(position < 30, "{}", position);
+        assert_eq!(controller.get_state(), Some(position as u8));
+    }
+
+    #[test]
+    fn reads_the_position_from_the_sensor() {
+        let rig = Rig::default();
+        let mut controller = rig
+            .controller(false)
+            .with_feedback(Some(Pot(&rig)), Some(POT));
+        assert_eq!(controller.get_state(), None);
+        block_on(controller.sync_position());
+        assert_eq!(controller.get_state(), Some(50));
+        // Moved by hand.
+        rig.position_ms.set(7_000);
+        block_on(controller.sync_position());
+        assert_eq!(controller.get_state(), Some(70));
+    }
+
+    #[test]
+    fn stops_at_the_target_by_the_sensor() {
+        let rig = Rig::default();
+        let mut controller = rig
+            .controller(false)
+            .with_feedback(Some(Pot(&rig)), Some(POT));
+        block_on(controller.sync_position());
+        // Timed, the move would stop at 26.
+        rig.slowdown_percent.set(20);
+        block_on(controller.set_state(20, rig.timed())).unwrap();
+        assert_eq!(controller.get_state(), Some(20));
+        assert_eq!(rig.position(), 20);
+        // The travel time only bounds the move.
+        assert_eq!(rig.moves(), [closing(12_000)]);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn measures_the_feedback_range() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false).with_feedback(Some(Pot(&rig)), None);
+        block_on(controller.calibrate(rig.timed())).unwrap();
+        assert_eq!(controller.feedback_range(), Some(POT));
+        assert_eq!(controller.get_state(), Some(0));
+        block_on(controller.set_state(40, rig.timed())).unwrap();
+        assert_eq!(controller.get_state(), Some(40));
+        assert!(rig.position_ms.get().abs_diff(4_000) <= 20);
+    }
+
+    #[test]
+    fn fails_calibration_with_a_stuck_sensor() {
+        let rig = Rig::default();
+        let mut controller = rig.controller(false).with_feedback(Some(Stuck), None);
+        let result = block_on(controller.calibrate(rig.timed()));
+        assert!(matches!(result, Err(Error::CalibrationFailed)));
+        assert_eq!(controller.feedback_range(), None);
+        assert_eq!(controller.get_state(), None);
+        assert!(rig.idle());
+    }
+
+    #[test]
+    fn re_references_encoder_counts_at_the_end_stop() {
+        let rig = Rig::default();
+        let mut controller = rig
+            .controller(false)
+            .with_feedback(Some(Encoder(&rig)), Some(POT));
+        // Counts say nothing before a calibration, whatever range is given.
+        assert_eq!(controller.feedback_range(), None);
+        block_on(controller.calibrate(rig.timed())).unwrap();
+        let counts = FeedbackRange {
+            closed: -500,
+            open: 500,
+        };
+        assert_eq!(controller.feedback_range(), Some(counts));
+        block_on(controller.set_state(30, rig.timed())).unwrap();
+        assert_eq!(rig.position(), 30);
+        // Noise adds counts: the encoder reads 5 more than the actuator is
+        // at, so closing runs into the end stop before the counts say 0.
+        rig.slip.set(50);
+        block_on(controller.set_state(0, rig.timed())).unwrap();
+        assert_eq!(rig.position_ms.get(), 0);
+        assert_eq!(controller.get_state(), Some(0));
+        let shifted = FeedbackRange {
+            closed: -450,
+            open: 550,
+        };
+        assert_eq!(controller.feedback_range(), Some(shifted));
+        block_on(controller.set_state(50, rig.timed())).unwrap();
+        assert_eq!(rig.position(), 50);
+    }
+}
@@ -0,0 +1,235 @@
+//! Speed ramps for PWM-driven moves: the duty cycle rises from an approach
+//! speed at the start of a move, falls back to it before the target, and
+//! stays there near the ends of travel, so the gearbox and the curtain are
+//! not jerked and the actuator meets an end stop slowly.
+//!
+//! Distances are measured in run time at full speed, which is what the
+//! controller estimates positions in; a ramp integrates the duty cycle over
+//! the time it ran to keep that estimate right at reduced speed.
+
+use embedded_hal_async::delay::DelayNs;
+
+/// Speed is given as a PWM duty cycle in percent, taken as proportional to
+/// the actuator's speed.
+#[derive(Debug, Clone, Copy)]
+pub struct RampSettings {
+    /// Time from the approach speed to full speed at the start of a move.
+    pub accel_ms: u64,
+    /// Run time at full speed before the target at which slowing down begins.
+    pub decel_ms: u64,
+    /// Lowest duty cycle used; the motor must still move reliably at it.
+    pub approach_duty: u8,
+    /// Run time at full speed before an end of travel within which the motor
+    /// runs at the approach speed.
+    pub approach_ms: u64,
+    /// Time between duty cycle updates.
+    pub step_ms: u32,
+}
+
+impl RampSettings {
+    fn floor(&self) -> u8 {
+        self.approach_duty.clamp(1, 100)
+    }
+}
+
+/// Stands in for the delay of a controller without ramps, which drives the
+/// motor at full speed; there are no values of it to wait on.
+pub enum NoRamps {}
+
+impl DelayNs for NoRamps {
+    async fn delay_ns(&mut self, _ns: u32) {
+        match *self {}
+    }
+}
+
+/// The speed profile of one move.
+#[derive(Debug, Clone, Copy)]
+pub struct RampProfile {
+    settings: RampSettings,
+    distance_ms: u64,
+    to_end_ms: Option<u64>,
+    elapsed_ms: u64,
+    /// Distance covered, in hundredths of a millisecond at full speed so
+    /// that short steps at low duty cycles are not rounded away.
+    travelled: u64,
+}
+
+impl RampProfile {
+    /// A move over `distance_ms` at full speed; `to_end_ms` is the distance
+    /// to the end of travel in the direction of the move, if known.
+    pub fn new(settings: RampSettings, distance_ms: u64, to_end_ms: Option<u64>) -> Self {
+        Self {
+            settings,
+            distance_ms,
+            to_end_ms,
+            elapsed_ms: 0,
+            travelled: 0,
+        }
+    }
+
+    /// Duty cycle, in percent, to drive the motor at now.
+    pub fn duty(&self) -> u8 {
+        let floor = self.settings.floor();
+        if self
+            .to_end_ms
+            .is_some_and(|end| end.saturating_sub(self.travelled_ms()) <= self.settings.approach_ms)
+        {
+            return floor;
+        }
+        let accel = ramp(floor, self.elapsed_ms, self.settings.accel_ms);
+        let remaining_ms = self.distance_ms.saturating_sub(self.travelled_ms());
+        let decel = ramp(floor, remaining_ms, self.settings.decel_ms);
+        accel.min(decel)
+    }
+
+    /// Accounts for `step_ms` run at the current duty cycle.
+    pub fn advance(&mut self, step_ms: u32) {
+        let step_ms = u64::from(step_ms);
+        self.travelled += step_ms * u64::from(self.duty());
+        self.elapsed_ms += step_ms;
+    }
+
+    /// Whether the move covered its distance.
+    pub fn arrived(&self) -> bool {
+        self.travelled >= self.distance_ms * 100
+    }
+
+    /// Distance covered so far, in run time at full speed.
+    pub fn travelled_ms(&self) -> u64 {
+        self.travelled / 100
+    }
+
+    /// Longest a move that would take `duration_ms` at full speed can take
+    /// with this profile: all of it at the approach speed, plus the step in
+    /// which it arrives.
+    pub fn time_bound(&self, duration_ms: u64) -> u64 {
+        duration_ms * 100 / u64::from(self.settings.floor()) + u64::from(self.settings.step_ms)
+    }
+}
+
+/// Rises linearly from `floor` to full duty as `progress_ms` goes to `over_ms`.
+fn ramp(floor: u8, progress_ms: u64, over_ms: u64) -> u8 {
+    if progress_ms >= over_ms {
+        return 100;
+    }
+    let rise = u64::from(100 - floor) * progress_ms / over_ms;
+    floor + rise as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: RampSettings = RampSettings {
+        accel_ms: 500,
+        decel_ms: 500,
+        approach_duty: 40,
+        approach_ms: 1_000,
+        step_ms: 20,
+    };
+    /// Full speed throughout, but for what a test turns on.
+    const FLAT: RampSettings = RampSettings {
+        accel_ms: 0,
+        decel_ms: 0,
+        approach_ms: 0,
+        ..SETTINGS
+    };
+
+    /// Advances `profile` until `until` holds, returning the elapsed time.
+    fn run(profile: &mut RampProfile, until: impl Fn(&RampProfile) -> bool) -> u64 {
+        let mut elapsed_ms = 0;
+        while !until(profile) {
+            profile.advance(SETTINGS.step_ms);
+            elapsed_ms += u64::from(SETTINGS.step_ms);
+        }
+        elapsed_ms
+    }
+
+    #[test]
+    fn integrates_the_distance_travelled() {
+        let mut profile = RampProfile::new(FLAT, 1_000, None);
+        profile.advance(100);
+        assert_eq!(profile.travelled_ms(), 100);
+        // At the approach speed, 100 ms cover 40 ms of the distance.
+        let mut profile = RampProfile::new(FLAT, 1_000, Some(0));
+        assert_eq!(profile.duty(), 40);
+        profile.advance(100);
+        assert_eq!(profile.travelled_ms(), 40);
+    }
+
+    #[test]
+    fn bounds_the_time_at_the_approach_speed() {
+        let profile = RampProfile::new(SETTINGS, 1_000, None);
+        assert_eq!(profile.time_bound(1_000), 2_520);
+        // A duty cycle of 0 would never arrive; it counts as 1.
+        let stalled = RampSettings {
+            approach_duty: 0,
+            ..SETTINGS
+        };
+        let profile = RampProfile::new(stalled, 1_000, None);
+        assert_eq!(profile.duty(), 1);
+        assert_eq!(profile.time_bound(1_000), 100_020);
+    }
+
+    #[test]
+    fn accelerates_from_the_approach_speed() {
+        let mut profile = RampProfile::new(SETTINGS, 10_000, None);
+        assert_eq!(profile.duty(), 40);
+        run(&mut profile, |p| p.duty() >= 70);
+        let half_way = profile.duty();
+        assert!((70..=72).contains(&half_way), "{}", half_way);
+        let elapsed_ms = run(&mut profile, |p| p.duty() == 100);
+        assert!(elapsed_ms <= 260, "{}", elapsed_ms);
+    }
+
+    #[test]
+    fn approaches_the_ends_slowly() {
+        let settings = RampSettings {
+            approach_ms: 1_000,
+            ..FLAT
+        };
+        let mut profile = RampProfile::new(settings, 3_000, Some(3_000));
+        assert_eq!(profile.duty(), 100);
+        run(&mut profile, |p| p.duty() < 100);
+        assert_eq!(profile.duty(), 40);
+        assert_eq!(profile.travelled_ms(), 2_000);
+        // The end is not known: full speed to the target.
+        let mut profile = RampProfile::new(settings, 3_000, None);
+        run(&mut profile, RampProfile::arrived);
+        assert_eq!(profile.duty(), 100);
+    }
+
+    #[test]
+    fn slows_down_before_the_target() {
+        let settings = RampSettings {
+            decel_ms: 500,
+            ..FLAT
+        };
+        let mut profile = RampProfile::new(settings, 2_000, None);
+        run(&mut profile, |p| p.duty() < 100);
+        // The first step inside the deceleration distance: 480 of its 500 ms
+        // to go.
+        assert_eq!(profile.travelled_ms(), 1_520);
+        assert_eq!(profile.duty(), 97);
+        run(&mut profile, |p| p.travelled_ms() >= 1_990);
+        assert!(profile.duty() <= 42, "{}", profile.duty());
+    }
+
+    #[test]
+    fn ramped_moves_end_at_their_distance() {
+        for distance_ms in [100, 999, 4_000, 12_345] {
+            let mut profile = RampProfile::new(SETTINGS, distance_ms, Some(distance_ms));
+            let elapsed_ms = run(&mut profile, RampProfile::arrived);
+            let travelled = profile.travelled_ms();
+            // Within the last step, at the approach speed.
+            assert!(travelled >= distance_ms, "{}", distance_ms);
+            assert!(
+                travelled < distance_ms + 8,
+                "{}: {}",
+                distance_ms,
+                travelled
+            );
+            assert!(elapsed_ms <= profile.time_bound(distance_ms));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Stall and overcurrent detection from motor current readings taken while a
+//! move runs.
+//!
+//! A starting motor draws an inrush current that looks just like a stall, so
+//! stall readings only count after a blanking time, and only once several
+//! follow each other. A reading at the overcurrent level trips at once.
+
+/// Thresholds are in whatever unit the caller reads the current in, such as
+/// raw ADC counts.
+#[derive(Debug, Clone, Copy)]
+pub struct StallSettings {
+    /// Time after the motor starts during which only overcurrent counts.
+    pub blanking_ms: u64,
+    pub stall_threshold: u16,
+    /// Readings above the stall threshold in a row before a stall counts.
+    pub stall_samples: u8,
+    pub overcurrent_threshold: u16,
+}
+
+/// What the controller does once a move stalled; the move fails with
+/// [`crate::Error::Stalled`] either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallAction {
+    /// Stop where it is; the estimated position is kept.
+    Stop,
+    /// Run the other way for `duration_ms` to take the load off the
+    /// mechanism, then stop.
+    Reverse { duration_ms: u64 },
+    /// Stop and forget the position, so it takes a calibration to move again.
+    Fault,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trip {
+    Stall,
+    Overcurrent,
+}
+
+/// Watches the readings of one move.
+pub struct StallDetector {
+    settings: StallSettings,
+    over: u8,
+}
+
+impl StallDetector {
+    pub fn new(settings: StallSettings) -> Self {
+        Self { settings, over: 0 }
+    }
+
+    /// Takes a reading taken `elapsed_ms` into the move.
+    pub fn sample(&mut self, elapsed_ms: u64, reading: u16) -> Option<Trip> {
+        if reading >= self.settings.overcurrent_threshold {
+            return Some(Trip::Overcurrent);
+        }
+        if elapsed_ms < self.settings.blanking_ms || reading < self.settings.stall_threshold {
+            self.over = 0;
+            return None;
+        }
+        self.over = self.over.saturating_add(1);
+        (self.over >= self.settings.stall_samples.max(1)).then_some(Trip::Stall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: StallSettings = StallSettings {
+        blanking_ms: 300,
+        stall_threshold: 2000,
+        stall_samples: 3,
+        overcurrent_threshold: 3500,
+    };
+
+    #[test]
+    fn ignores_inrush_while_blanked() {
+        let mut detector = StallDetector::new(SETTINGS);
+        for elapsed_ms in (0..300).step_by(10) {
+            assert_eq!(detector.sample(elapsed_ms, 3000), None);
+        }
+        // Blanked readings do not count toward the stall.
+        assert_eq!(detector.sample(300, 3000), None);
+        assert_eq!(detector.sample(310, 3000), None);
+        assert_eq!(detector.sample(320, 3000), Some(Trip::Stall));
+    }
+
+    #[test]
+    fn needs_consecutive_readings_to_stall() {
+        let mut detector = StallDetector::new(SETTINGS);
+        assert_eq!(detector.sample(400, 2000), None);
+        assert_eq!(detector.sample(410, 2500), None);
+        assert_eq!(detector.sample(420, 1999), None);
+        assert_eq!(detector.sample(430, 2000), None);
+        assert_eq!(detector.sample(440, 2000), None);
+        assert_eq!(detector.sample(450, 2000), Some(Trip::Stall));
+    }
+
+    #[test]
+    fn trips_on_overcurrent_at_once() {
+        let mut detector = StallDetector::new(SETTINGS);
+        assert_eq!(detector.sample(0, 3500), Some(Trip::Overcurrent));
+        let mut detector = StallDetector::new(SETTINGS);
+        assert_eq!(detector.sample(400, 2500), None);
+        assert_eq!(detector.sample(410, u16::MAX), Some(Trip::Overcurrent));
+    }
+
+    #[test]
+    fn takes_zero_samples_as_one() {
+        let settings = StallSettings {
+            stall_samples: 0,
+            ..SETTINGS
+        };
+        let mut detector = StallDetector::new(settings);
+        assert_eq!(detector.sample(400, 1999), None);
+        assert_eq!(detector.sample(410, 2000), Some(Trip::Stall));
+    }
+}
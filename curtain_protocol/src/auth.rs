@@ -0,0 +1,364 @@
+//! Command authentication with a secret shared by the device and the server.
+//!
+//! A device with a secret sends a random `challenge` in its `register` frame.
+//! From then on every command, and every `resume` and `set_framing`, must carry
+//! a `counter`, strictly increasing within the connection, and a `mac`:
+//! HMAC-SHA256 keyed with the secret over
+//!
+//! ```text
+//! challenge (u32 BE) | counter (u32 BE) | message
+//! ```
+//!
+//! where `message` is one type byte (`set_value` 0, `get_value` 1,
+//! `calibrate` 2, `stop` 3), the `id` as a big-endian `u32` and, for
+//! `set_value`, the `value` likewise. A `resume` (type 4) is followed by its
+//! `session` and `seq`, big-endian `u32`s, and a `set_framing` (type 5) by the
+//! framing's name. JSON frames carry the MAC as 64 hex digits; postcard frames
+//! append the counter (`u32` LE) and the 32 MAC bytes to the encoded command.
+//! The challenge binds messages to one connection, so a recorded one cannot be
+//! replayed on a later connection either.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::Command;
+
+pub const MAC_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Why a command was rejected; answered with an `auth_failed` reply.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// No counter or MAC, or a MAC that is not 64 hex digits.
+    Missing,
+    BadMac,
+    /// The counter did not increase; the command is a replay.
+    Replayed,
+}
+
+/// Everything a MAC can cover: the commands, and the frames that steer the
+/// connection.
+#[derive(Debug, Clone, Copy)]
+pub enum Message<'a> {
+    Command(&'a Command),
+    Resume { session: u32, seq: u32 },
+    SetFraming(&'a str),
+}
+
+impl<'a> From<&'a Command> for Message<'a> {
+    fn from(command: &'a Command) -> Self {
+        Self::Command(command)
+    }
+}
+
+/// MAC of `message` sent with `counter` on the connection with `challenge`.
+pub fn sign<'m>(
+    secret: &[u8],
+    challenge: u32,
+    counter: u32,
+    message: impl Into<Message<'m>>,
+) -> [u8; MAC_LEN] {
+    mac(secret, challenge, counter, message.into())
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+fn mac(secret: &[u8], challenge: u32, counter: u32, message: Message<'_>) -> HmacSha256 {
+    // HMAC accepts keys of any length.
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC key of any length");
+    mac.update(&challenge.to_be_bytes());
+    mac.update(&counter.to_be_bytes());
+    let command = match message {
+        Message::Command(command) => command,
+        Message::Resume { session, seq } => {
+            mac.update(&[4]);
+            mac.update(&session.to_be_bytes());
+            mac.update(&seq.to_be_bytes());
+            return mac;
+        }
+        Message::SetFraming(framing) => {
+            mac.update(&[5]);
+            mac.update(framing.as_bytes());
+            return mac;
+        }
+    };
+    let (tag, id, value) = match *command {
+        Command::SetValue { id, value } => (0, id, Some(value)),
+        Command::GetValue { id } => (1, id, None),
+        Command::Calibrate { id } => (2, id, None),
+        Command::Stop { id } => (3, id, None),
+    };
+    mac.update(&[tag]);
+    mac.update(&id.to_be_bytes());
+    if let Some(value) = value {
+        mac.update(&value.to_be_bytes());
+    }
+    mac
+}
+
+/// Checks the messages of one connection.
+pub struct Verifier<'a> {
+    secret: &'a [u8],
+    challenge: u32,
+    last_counter: Option<u32>,
+}
+
+impl<'a> Verifier<'a> {
+    /// `challenge` must be freshly random for every connection.
+    pub fn new(secret: &'a [u8], challenge: u32) -> Self {
+        Self {
+            secret,
+            challenge,
+            last_counter: None,
+        }
+    }
+
+    pub fn challenge(&self) -> u32 {
+        self.challenge
+    }
+
+    /// Accepts `message` if `mac` is valid and `counter` is higher than that of
+    /// every message accepted before.
+    pub fn verify<'m>(
+        &mut self,
+        message: impl Into<Message<'m>>,
+        counter: u32,
+        mac: &[u8],
+    ) -> Result<(), AuthError> {
+        self::mac(self.secret, self.challenge, counter, message.into())
+            .verify_slice(mac)
+            .map_err(|_| AuthError::BadMac)?;
+        if self.last_counter.is_some_and(|last| counter <= last) {
+            return Err(AuthError::Replayed);
+        }
+        self.last_counter = Some(counter);
+        Ok(())
+    }
+
+    /// [`Verifier::verify`] for a JSON frame with its hex `mac` field.
+    pub fn verify_hex<'m>(
+        &mut self,
+        message: impl Into<Message<'m>>,
+        counter: Option<u32>,
+        mac: Option<&str>,
+    ) -> Result<(), AuthError> {
+        let (Some(counter), Some(mac)) = (counter, mac.and_then(decode_hex)) else {
+            return Err(AuthError::Missing);
+        };
+        self.verify(message, counter, &mac)
+    }
+}
+
+fn decode_hex(s: &str) -> Option<[u8; MAC_LEN]> {
+    if s.len() != 2 * MAC_LEN {
+        return None;
+    }
+    let mut out = [0u8; MAC_LEN];
+    let digit = |c: u8| char::from(c).to_digit(16);
+    for (byte, pair) in out.iter_mut().zip(s.as_bytes().chunks(2)) {
+        *byte = (digit(pair[0])? << 4 | digit(pair[1])?) as u8;
+    }
+    Some(out)
+}
+
+/// Writes `mac` as the 64 hex digits of the JSON `mac` field.
+pub fn encode_hex(mac: &[u8; MAC_LEN]) -> [u8; 2 * MAC_LEN] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = [0u8; 2 * MAC_LEN];
+    for (pair, byte) in out.chunks_mut(2).zip(mac) {
+        pair[0] = DIGITS[usize::from(byte >> 4)];
+        pair[1] = DIGITS[usize::from(byte & 0x0F)];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const CHALLENGE: u32 = 0x0102_0304;
+    const SET_40: Command = Command::SetValue { id: 1, value: 40 };
+    /// HMAC-SHA256 under `SECRET` of `01020304 00000007 00 00000001 00000028`.
+    const SET_40_MAC: &str = "a1b7026dd6f34eec014242952cbf2c3ff6d4ca5d50100aec953fa8a5284fd4d4";
+    /// Of `01020304 00000008 04 deadbeef 00000011`.
+    const RESUME_MAC: &str = "9ee581bf990d00d0163086ffc3035e0f20f0494aff46805e66d648c8c364abfa";
+    /// Of `01020304 00000009 05` and `postcard`.
+    const SET_FRAMING_MAC: &str =
+        "9af33e63829c5e78e55b9107b95219d93f423cfe65caa5fab460344a7d78fd09";
+
+    #[test]
+    fn signs_fixed_vectors() {
+        let resume = Message::Resume {
+            session: 0xDEAD_BEEF,
+            seq: 17,
+        };
+        let set_framing = Message::SetFraming("postcard");
+        assert_eq!(
+            &encode_hex(&sign(SECRET, CHALLENGE, 7, &SET_40)),
+            SET_40_MAC.as_bytes()
+        );
+        assert_eq!(
+            &encode_hex(&sign(SECRET, CHALLENGE, 8, resume)),
+            RESUME_MAC.as_bytes()
+        );
+        assert_eq!(
+            &encode_hex(&sign(SECRET, CHALLENGE, 9, set_framing)),
+            SET_FRAMING_MAC.as_bytes()
+        );
+    }
+
+    #[test]
+    fn verifies_increasing_counters() {
+        let mut verifier = Verifier::new(SECRET, CHALLENGE);
+        assert_eq!(
+            verifier.verify_hex(&SET_40, Some(7), Some(SET_40_MAC)),
+            Ok(())
+        );
+        let stop = Command::Stop { id: 2 };
+        let mac = sign(SECRET, CHALLENGE, 8, &stop);
+        assert_eq!(verifier.verify(&stop, 8, &mac), Ok(()));
+    }
+
+    #[test]
+    fn rejects_replayed_counter() {
+        let mut verifier = Verifier::new(SECRET, CHALLENGE);
+        assert_eq!(
+            verifier.verify_hex(&SET_40, Some(7), Some(SET_40_MAC)),
+            Ok(())
+        );
+        assert_eq!(
+            verifier.verify_hex(&SET_40, Some(7), Some(SET_40_MAC)),
+            Err(AuthError::Replayed)
+        );
+        let stop = Command::Stop { id: 2 };
+        let mac = sign(SECRET, CHALLENGE, 6, &stop);
+        assert_eq!(verifier.verify(&stop, 6, &mac), Err(AuthError::Replayed));
+    }
+
+    #[test]
+    fn rejects_wrong_mac() {
+        let mut verifier = Verifier::new(SECRET, CHALLENGE);
+        let mut mac = sign(SECRET, CHALLENGE, 7, &SET_40);
+        mac[0] ^= 1;
+        assert_eq!(verifier.verify(&SET_40, 7, &mac), Err(AuthError::BadMac));
+        // Another command, counter, challenge or secret under the same MAC.
+        let mac = sign(SECRET, CHALLENGE, 7, &SET_40);
+        let set_41 = Command::SetValue { id: 1, value: 41 };
+        assert_eq!(verifier.verify(&set_41, 7, &mac), Err(AuthError::BadMac));
+        assert_eq!(verifier.verify(&SET_40, 8, &mac), Err(AuthError::BadMac));
+        let mut other = Verifier::new(SECRET, CHALLENGE + 1);
+        assert_eq!(other.verify(&SET_40, 7, &mac), Err(AuthError::BadMac));
+        let mut other = Verifier::new(b"secreT", CHALLENGE);
+        assert_eq!(other.verify(&SET_40, 7, &mac), Err(AuthError::BadMac));
+        // A failed check does not advance the counter.
+        assert_eq!(verifier.verify(&SET_40, 7, &mac), Ok(()));
+    }
+
+    #[test]
+    fn rejects_missing_or_malformed_mac() {
+        let mut verifier = Verifier::new(SECRET, CHALLENGE);
+        assert_eq!(
+            verifier.verify_hex(&SET_40, None, Some(SET_40_MAC)),
+            Err(AuthError::Missing)
+        );
+        assert_eq!(
+            verifier.verify_hex(&SET_40, Some(7), None),
+            Err(AuthError::Missing)
+        );
+        let short = &SET_40_MAC[..62];
+        assert_eq!(
+            verifier.verify_hex(&SET_40, Some(7), Some(short)),
+            Err(AuthError::Missing)
+        );
+        let mut bad_digit = *b"a1b7026dd6f34eec014242952cbf2c3ff6d4ca5d50100aec953fa8a5284fd4dg";
+        let bad_digit = core::str::from_utf8_mut(&mut bad_digit).unwrap();
+        assert_eq!(
+            verifier.verify_hex(&SET_40, Some(7), Some(bad_digit)),
+            Err(AuthError::Missing)
+        );
+        let mac = sign(SECRET, CHALLENGE, 7, &SET_40);
+        assert_eq!(
+            verifier.verify(&SET_40, 7, &mac[..31]),
+            Err(AuthError::BadMac)
+        );
+    }
+
+    #[test]
+    fn binds_connection_frames() {
+        let mut verifier = Verifier::new(SECRET, CHALLENGE);
+        let resume = Message::Resume {
+            session: 0xDEAD_BEEF,
+            seq: 17,
+        };
+        let other = Message::Resume {
+            session: 0xDEAD_BEEF,
+            seq: 18,
+        };
+        assert_eq!(
+            verifier.verify_hex(other, Some(8), Some(RESUME_MAC)),
+            Err(AuthError::BadMac)
+        );
+        assert_eq!(
+            verifier.verify_hex(resume, Some(8), Some(RESUME_MAC)),
+            Ok(())
+        );
+        let json = Message::SetFraming("json");
+        assert_eq!(
+            verifier.verify_hex(json, Some(9), Some(SET_FRAMING_MAC)),
+            Err(AuthError::BadMac)
+        );
+        let postcard = Message::SetFraming("postcard");
+        assert_eq!(
+            verifier.verify_hex(postcard, Some(9), Some(SET_FRAMING_MAC)),
+            Ok(())
+        );
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn decodes_authenticated_frames() {
+        use crate::binary::{self, Frame, FrameBuffer};
+
+        let mut buf = [0u8; binary::MAX_ENCODED_LEN];
+        let len = binary::encode_command(&SET_40, &mut buf).unwrap();
+        let mac = sign(SECRET, CHALLENGE, 7, &SET_40);
+        let mut payload = [0u8; binary::MAX_FRAME_LEN];
+        let command_len = len - 2;
+        payload[..command_len].copy_from_slice(&buf[2..len]);
+        payload[command_len..command_len + 4].copy_from_slice(&7u32.to_le_bytes());
+        payload[command_len + 4..command_len + 4 + MAC_LEN].copy_from_slice(&mac);
+        let payload = &payload[..command_len + 4 + MAC_LEN];
+
+        let (command, counter, mac) = binary::decode_authenticated_command(payload).unwrap();
+        assert!(matches!(command, Command::SetValue { id: 1, value: 40 }));
+        assert_eq!(counter, 7);
+        let mut verifier = Verifier::new(SECRET, CHALLENGE);
+        assert_eq!(verifier.verify(&command, counter, mac), Ok(()));
+
+        // Truncated anywhere: in the MAC, the counter or the command.
+        for len in 0..payload.len() {
+            assert!(
+                binary::decode_authenticated_command(&payload[..len]).is_none(),
+                "{len}"
+            );
+        }
+        // A frame with trailing bytes after the MAC is not one either.
+        let mut long = [0u8; 64];
+        long[..payload.len()].copy_from_slice(payload);
+        assert!(binary::decode_authenticated_command(&long[..payload.len() + 1]).is_none());
+
+        // And the same frame arriving through the reassembly buffer.
+        let mut frames = FrameBuffer::<{ binary::MAX_FRAME_LEN }>::new();
+        let header = (payload.len() as u16).to_le_bytes();
+        let mut complete = None;
+        for &b in header.iter().chain(payload) {
+            if let Some(Frame::Complete(frame)) = frames.push(b) {
+                complete = Some(binary::decode_authenticated_command(frame).is_some());
+            }
+        }
+        assert_eq!(complete, Some(true));
+    }
+}
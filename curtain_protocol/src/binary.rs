@@ -0,0 +1,375 @@
+//! Length-prefixed postcard framing.
+//!
+//! The device offers it by sending `"framing":"postcard"` in its JSON
+//! `register` frame. A server that supports it answers with the JSON line
+//! `{"type":"set_framing","framing":"postcard"}`; every byte after that line,
+//! in both directions, is a sequence of frames made of a little-endian `u16`
+//! payload length followed by the postcard-encoded payload.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Command, Reply};
+
+/// Value of the `framing` field that selects this encoding.
+pub const FRAMING: &str = "postcard";
+
+/// Largest payload either side will send or accept.
+pub const MAX_FRAME_LEN: usize = 256;
+
+const HEADER_LEN: usize = 2;
+
+/// Largest complete frame, including the length prefix.
+pub const MAX_ENCODED_LEN: usize = HEADER_LEN + MAX_FRAME_LEN;
+
+/// Externally tagged mirror of [`Reply`]; postcard is not self-describing and
+/// cannot decode `#[serde(tag)]` enums.
+#[derive(Serialize, Deserialize)]
+enum WireReply<'a> {
+    Register {
+        uuid: &'a str,
+        challenge: Option<u32>,
+        session: Option<u32>,
+    },
+    Ack {
+        id: u32,
+        ok: bool,
+    },
+    Value {
+        id: u32,
+        value: u8,
+    },
+    Error {
+        id: u32,
+        message: &'a str,
+    },
+    Status {
+        value: Option<u8>,
+    },
+    AuthFailed {
+        id: u32,
+    },
+    Local {
+        action: &'a str,
+        error: Option<&'a str>,
+    },
+    Fault {
+        error: &'a str,
+    },
+    ResumeFailed,
+}
+
+impl<'a> From<&Reply<'a>> for WireReply<'a> {
+    fn from(reply: &Reply<'a>) -> Self {
+        match *reply {
+            Reply::Register {
+                uuid,
+                challenge,
+                session,
+                ..
+            } => Self::Register {
+                uuid,
+                challenge,
+                session,
+            },
+            Reply::Ack { id, ok } => Self::Ack { id, ok },
+            Reply::Value { id, value } => Self::Value { id, value },
+            Reply::Error { id, message } => Self::Error { id, message },
+            Reply::Status { value } => Self::Status { value },
+            Reply::AuthFailed { id } => Self::AuthFailed { id },
+            Reply::Local { action, error } => Self::Local { action, error },
+            Reply::Fault { error } => Self::Fault { error },
+            Reply::ResumeFailed => Self::ResumeFailed,
+        }
+    }
+}
+
+impl<'a> From<WireReply<'a>> for Reply<'a> {
+    fn from(reply: WireReply<'a>) -> Self {
+        match reply {
+            WireReply::Register {
+                uuid,
+                challenge,
+                session,
+            } => Self::Register {
+                uuid,
+                framing: Some(FRAMING),
+                challenge,
+                session,
+            },
+            WireReply::Ack { id, ok } => Self::Ack { id, ok },
+            WireReply::Value { id, value } => Self::Value { id, value },
+            WireReply::Error { id, message } => Self::Error { id, message },
+            WireReply::Status { value } => Self::Status { value },
+            WireReply::AuthFailed { id } => Self::AuthFailed { id },
+            WireReply::Local { action, error } => Self::Local { action, error },
+            WireReply::Fault { error } => Self::Fault { error },
+            WireReply::ResumeFailed => Self::ResumeFailed,
+        }
+    }
+}
+
+pub fn decode_command(payload: &[u8]) -> Option<Command> {
+    postcard::from_bytes(payload).ok()
+}
+
+/// Decodes a command followed by its counter and MAC, see [`crate::auth`].
+#[cfg(feature = "auth")]
+pub fn decode_authenticated_command(payload: &[u8]) -> Option<(Command, u32, &[u8])> {
+    let (cmd, rest) = postcard::take_from_bytes(payload).ok()?;
+    let (counter, mac) = rest.split_first_chunk::<4>()?;
+    (mac.len() == crate::auth::MAC_LEN).then(|| (cmd, u32::from_le_bytes(*counter), mac))
+}
+
+pub fn decode_reply(payload: &[u8]) -> Option<Reply<'_>> {
+    postcard::from_bytes::<WireReply>(payload)
+        .ok()
+        .map(Reply::from)
+}
+
+/// Decodes an event written by [`encode_sequenced_reply`].
+pub fn decode_sequenced_reply(payload: &[u8]) -> Option<(u32, Reply<'_>)> {
+    postcard::from_bytes::<(u32, WireReply)>(payload)
+        .ok()
+        .map(|(seq, reply)| (seq, Reply::from(reply)))
+}
+
+/// Writes `cmd` as a complete frame into `buf`, returning the frame length.
+pub fn encode_command(cmd: &Command, buf: &mut [u8]) -> Option<usize> {
+    encode_frame(cmd, buf)
+}
+
+/// Writes `reply` as a complete frame into `buf`, returning the frame length.
+pub fn encode_reply(reply: &Reply, buf: &mut [u8]) -> Option<usize> {
+    encode_frame(&WireReply::from(reply), buf)
+}
+
+/// Writes `reply` as a complete frame led by the event's sequence number.
+pub fn encode_sequenced_reply(seq: u32, reply: &Reply, buf: &mut [u8]) -> Option<usize> {
+    encode_frame(&(seq, WireReply::from(reply)), buf)
+}
+
+fn encode_frame<T: Serialize>(value: &T, buf: &mut [u8]) -> Option<usize> {
+    let (header, payload) = buf.split_at_mut_checked(HEADER_LEN)?;
+    let len = postcard::to_slice(value, payload).ok()?.len();
+    if len > MAX_FRAME_LEN {
+        return None;
+    }
+    header.copy_from_slice(&(len as u16).to_le_bytes());
+    Some(HEADER_LEN + len)
+}
+
+/// Result of feeding one byte into a [`FrameBuffer`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Frame<'a> {
+    /// A complete payload, without the length prefix.
+    Complete(&'a [u8]),
+    /// The announced payload length exceeds the buffer; the payload is skipped.
+    Oversized(usize),
+}
+
+/// Reassembles length-prefixed frames from arbitrarily chunked socket reads.
+pub struct FrameBuffer<const N: usize> {
+    buf: [u8; N],
+    header: [u8; HEADER_LEN],
+    header_len: usize,
+    expected: usize,
+    len: usize,
+}
+
+impl<const N: usize> FrameBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            header: [0; HEADER_LEN],
+            header_len: 0,
+            expected: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, b: u8) -> Option<Frame<'_>> {
+        if self.header_len < HEADER_LEN {
+            self.header[self.header_len] = b;
+            self.header_len += 1;
+            if self.header_len < HEADER_LEN {
+                return None;
+            }
+            self.expected = usize::from(u16::from_le_bytes(self.header));
+            return match self.expected {
+                0 => {
+                    self.header_len = 0;
+                    Some(Frame::Complete(&[]))
+                }
+                n if n > N => Some(Frame::Oversized(n)),
+                _ => None,
+            };
+        }
+
+        // oversized payloads are counted but not stored
+        if self.len < N {
+            self.buf[self.len] = b;
+        }
+        self.len += 1;
+        if self.len < self.expected {
+            return None;
+        }
+
+        let len = core::mem::take(&mut self.len);
+        self.header_len = 0;
+        (len <= N).then(|| Frame::Complete(&self.buf[..len]))
+    }
+}
+
+impl<const N: usize> Default for FrameBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `bytes` through `frames`, returning the first payload completed.
+    fn reassemble<'a, const N: usize>(
+        frames: &'a mut FrameBuffer<N>,
+        bytes: &[u8],
+    ) -> Option<&'a [u8]> {
+        let (&last, rest) = bytes.split_last()?;
+        for &b in rest {
+            assert_eq!(frames.push(b), None);
+        }
+        match frames.push(last) {
+            Some(Frame::Complete(payload)) => Some(payload),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn round_trips_commands() {
+        let mut buf = [0u8; MAX_ENCODED_LEN];
+        let len = encode_command(&Command::SetValue { id: 7, value: 40 }, &mut buf).unwrap();
+        assert_eq!(usize::from(u16::from_le_bytes([buf[0], buf[1]])), len - 2);
+        let mut frames = FrameBuffer::<MAX_FRAME_LEN>::new();
+        let payload = reassemble(&mut frames, &buf[..len]).unwrap();
+        assert!(matches!(
+            decode_command(payload),
+            Some(Command::SetValue { id: 7, value: 40 })
+        ));
+    }
+
+    #[test]
+    fn round_trips_replies() {
+        let mut buf = [0u8; MAX_ENCODED_LEN];
+        let mut frames = FrameBuffer::<MAX_FRAME_LEN>::new();
+        let error = Reply::Error {
+            id: 3,
+            message: "not calibrated",
+        };
+        let len = encode_reply(&error, &mut buf).unwrap();
+        let payload = reassemble(&mut frames, &buf[..len]).unwrap();
+        assert!(matches!(
+            decode_reply(payload),
+            Some(Reply::Error {
+                id: 3,
+                message: "not calibrated"
+            })
+        ));
+
+        let len = encode_sequenced_reply(9, &Reply::Status { value: None }, &mut buf).unwrap();
+        let payload = reassemble(&mut frames, &buf[..len]).unwrap();
+        assert!(matches!(
+            decode_sequenced_reply(payload),
+            Some((9, Reply::Status { value: None }))
+        ));
+    }
+
+    #[test]
+    fn register_offers_this_framing() {
+        let register = Reply::Register {
+            uuid: "8a3a3b0e",
+            framing: None,
+            challenge: Some(5),
+            session: None,
+        };
+        let mut buf = [0u8; MAX_ENCODED_LEN];
+        let len = encode_reply(&register, &mut buf).unwrap();
+        let decoded = decode_reply(&buf[HEADER_LEN..len]);
+        assert!(matches!(
+            decoded,
+            Some(Reply::Register {
+                uuid: "8a3a3b0e",
+                framing: Some(FRAMING),
+                challenge: Some(5),
+                session: None,
+            })
+        ));
+    }
+
+    #[test]
+    fn reassembles_back_to_back_frames() {
+        let mut buf = [0u8; 2 * MAX_ENCODED_LEN];
+        let first = encode_command(&Command::GetValue { id: 1 }, &mut buf).unwrap();
+        let second = encode_command(&Command::Stop { id: 2 }, &mut buf[first..]).unwrap();
+        let mut frames = FrameBuffer::<MAX_FRAME_LEN>::new();
+        let payload = reassemble(&mut frames, &buf[..first]).unwrap();
+        assert!(matches!(
+            decode_command(payload),
+            Some(Command::GetValue { id: 1 })
+        ));
+        let payload = reassemble(&mut frames, &buf[first..first + second]).unwrap();
+        assert!(matches!(
+            decode_command(payload),
+            Some(Command::Stop { id: 2 })
+        ));
+        // An empty frame is complete with its header.
+        assert_eq!(frames.push(0), None);
+        assert_eq!(frames.push(0), Some(Frame::Complete(&[])));
+    }
+
+    #[test]
+    fn skips_oversized_frames() {
+        let mut frames = FrameBuffer::<4>::new();
+        assert_eq!(frames.push(6), None);
+        assert_eq!(frames.push(0), Some(Frame::Oversized(6)));
+        for b in 1..=6 {
+            assert_eq!(frames.push(b), None);
+        }
+        // The next header is read where the skipped payload ended.
+        assert_eq!(
+            reassemble(&mut frames, &[2, 0, 0xAB, 0xCD]),
+            Some(&[0xAB, 0xCD][..])
+        );
+    }
+
+    #[test]
+    fn refuses_frames_that_do_not_fit() {
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            encode_command(&Command::GetValue { id: 1 }, &mut buf[..1]),
+            None
+        );
+        assert_eq!(
+            encode_command(
+                &Command::SetValue {
+                    id: u32::MAX,
+                    value: u32::MAX
+                },
+                &mut buf
+            ),
+            None
+        );
+        let message = core::str::from_utf8(&[b'x'; MAX_FRAME_LEN]).unwrap();
+        let mut buf = [0u8; 2 * MAX_ENCODED_LEN];
+        let error = Reply::Error { id: 1, message };
+        assert_eq!(encode_reply(&error, &mut buf), None);
+    }
+
+    #[test]
+    fn rejects_undecodable_payloads() {
+        assert!(decode_command(&[]).is_none());
+        assert!(decode_command(&[0xFF]).is_none());
+        assert!(decode_reply(&[0x7F]).is_none());
+        assert!(decode_sequenced_reply(&[1]).is_none());
+    }
+}
@@ -0,0 +1,35 @@
+//! UDP discovery. A client broadcasts `{"type":"discover"}` to
+//! [`DISCOVERY_PORT`] and every device answers it with an [`Announce`]; devices
+//! also broadcast one periodically on their own.
+
+use serde::Serialize;
+
+use crate::parse_command;
+
+/// UDP port devices listen on for discovery requests and announce to.
+pub const DISCOVERY_PORT: u16 = 9001;
+
+/// A device's answer to a discovery request, also broadcast unprompted.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "announce")]
+pub struct Announce<'a> {
+    pub uuid: &'a str,
+    /// Dotted IPv4 address of the device.
+    pub ip: &'a str,
+    /// Firmware version.
+    pub version: &'a str,
+    /// Current position; `None` while uncalibrated.
+    pub value: Option<u8>,
+}
+
+pub fn is_discover_request(datagram: &[u8]) -> bool {
+    core::str::from_utf8(datagram)
+        .ok()
+        .and_then(|s| parse_command(s.trim_end()))
+        .is_some_and(|cmd| cmd.cmd_type == "discover")
+}
+
+/// Writes `announce` as a JSON datagram into `buf`, returning its length.
+pub fn encode_announce(announce: &Announce, buf: &mut [u8]) -> Option<usize> {
+    serde_json_core::to_slice(announce, buf).ok()
+}
@@ -0,0 +1,196 @@
+//! Wire format shared by the curtain firmware and the server.
+//!
+//! Frames are newline-delimited JSON objects tagged by a `"type"` field. With the
+//! `postcard` feature the device may offer length-prefixed binary framing in its
+//! `register` frame, see [`binary`]. With the `auth` feature commands can be
+//! authenticated with a shared secret, see [`auth`]. This crate has no hardware or network
+//! dependencies so it builds for the ESP32 and the host alike.
+#![no_std]
+
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "postcard")]
+pub mod binary;
+pub mod discovery;
+pub mod line_buffer;
+
+use serde::{Deserialize, Serialize};
+
+/// A single command frame as sent by the server.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IncomingCommand<'a> {
+    #[serde(rename = "type")]
+    pub cmd_type: &'a str,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u32>,
+    /// Framing to switch to after this line, sent with `"type":"set_framing"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framing: Option<&'a str>,
+    /// Increasing per connection; required with the MAC on devices with a
+    /// secret.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter: Option<u32>,
+    /// HMAC-SHA256 of the command as 64 hex digits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<&'a str>,
+    /// Session token from the device's `register`, sent with `"type":"resume"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<u32>,
+    /// Last sequence number seen, sent with `"type":"resume"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+}
+
+/// The command set of [`IncomingCommand`], typed and validated for presence of
+/// the fields each command needs.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Command {
+    SetValue {
+        id: u32,
+        value: u32,
+    },
+    GetValue {
+        id: u32,
+    },
+    Calibrate {
+        id: u32,
+    },
+    /// Cuts power to the actuator.
+    Stop {
+        id: u32,
+    },
+}
+
+impl Command {
+    /// The id the reply to this command will carry.
+    pub fn id(&self) -> u32 {
+        match *self {
+            Self::SetValue { id, .. }
+            | Self::GetValue { id }
+            | Self::Calibrate { id }
+            | Self::Stop { id } => id,
+        }
+    }
+}
+
+/// A frame sent by the device, either unsolicited or in reply to a command `id`.
+///
+/// All but `register` are events numbered by the device, see
+/// [`encode_sequenced_reply`]. A device keeps its session token until it
+/// reboots; a server that reconnects to the same session answers `register`
+/// with `{"type":"resume","session":<token>,"seq":<last seen>}` and the device
+/// replays the events it still holds after that number. If it no longer holds
+/// all of them, or does not know the token, `resume_failed` comes first.
+/// Either way `resume` must be the server's first frame, before any command.
+///
+/// Decoding the internally tagged form needs an allocator, so `Deserialize` is
+/// only derived with the `alloc` feature (as used by the server).
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "alloc", derive(Deserialize))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Reply<'a> {
+    Register {
+        uuid: &'a str,
+        /// Binary framing the device can switch to, if any.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        framing: Option<&'a str>,
+        /// Bound into the MAC of every command; sent by devices with a secret.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        challenge: Option<u32>,
+        /// Random per boot; a different token means earlier events are lost.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session: Option<u32>,
+    },
+    Ack {
+        id: u32,
+        ok: bool,
+    },
+    Value {
+        id: u32,
+        value: u8,
+    },
+    Error {
+        id: u32,
+        message: &'a str,
+    },
+    /// Pushed whenever the position changes; `None` while uncalibrated.
+    Status {
+        value: Option<u8>,
+    },
+    /// The command `id` was not executed: its MAC was missing or wrong, or it
+    /// was a replay.
+    AuthFailed {
+        id: u32,
+    },
+    /// An action taken on the device itself, such as a button press; `error`
+    /// is set if the motor refused it.
+    Local {
+        action: &'a str,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<&'a str>,
+    },
+    /// The motor stopped on a fault, such as a stall, whatever asked it to
+    /// move; the position that resulted follows as a `status` event.
+    Fault {
+        error: &'a str,
+    },
+    /// A `resume` could not replay everything the server missed; what the
+    /// device still holds follows, then a `status` event.
+    ResumeFailed,
+}
+
+/// Why an [`IncomingCommand`] is not a [`Command`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// Unknown type, or a command without an `id`; ignored by the device.
+    Unsupported,
+    /// A `set_value` without a `value`.
+    MissingValue { id: u32 },
+}
+
+impl TryFrom<&IncomingCommand<'_>> for Command {
+    type Error = CommandError;
+
+    fn try_from(cmd: &IncomingCommand<'_>) -> Result<Self, Self::Error> {
+        match (cmd.cmd_type, cmd.id) {
+            ("set_value", Some(id)) => match cmd.value {
+                Some(value) => Ok(Self::SetValue { id, value }),
+                None => Err(CommandError::MissingValue { id }),
+            },
+            ("get_value", Some(id)) => Ok(Self::GetValue { id }),
+            ("calibrate", Some(id)) => Ok(Self::Calibrate { id }),
+            ("stop", Some(id)) => Ok(Self::Stop { id }),
+            _ => Err(CommandError::Unsupported),
+        }
+    }
+}
+
+pub fn parse_command(line: &str) -> Option<IncomingCommand<'_>> {
+    serde_json_core::de::from_str::<IncomingCommand>(line)
+        .ok()
+        .map(|(cmd, _rest)| cmd)
+}
+
+/// Writes `reply` as one newline-terminated JSON line into `buf`, returning the
+/// line length.
+pub fn encode_reply(reply: &Reply, buf: &mut [u8]) -> Option<usize> {
+    let body = buf.len().checked_sub(1)?;
+    let len = serde_json_core::to_slice(reply, &mut buf[..body]).ok()?;
+    buf[len] = b'\n';
+    Some(len + 1)
+}
+
+/// Like [`encode_reply`], with the event's sequence number as a leading `seq`
+/// field: `{"seq":7,"type":"status","value":40}`.
+pub fn encode_sequenced_reply(seq: u32, reply: &Reply, buf: &mut [u8]) -> Option<usize> {
+    const SEQ_KEY: &[u8] = b"{\"seq\":";
+    buf.get_mut(..SEQ_KEY.len())?.copy_from_slice(SEQ_KEY);
+    let prefix_len =
+        SEQ_KEY.len() + serde_json_core::to_slice(&seq, &mut buf[SEQ_KEY.len()..]).ok()?;
+    // The reply's own `{` becomes the separating comma.
+    let len = encode_reply(reply, buf.get_mut(prefix_len..)?)?;
+    buf[prefix_len] = b',';
+    Some(prefix_len + len)
+}
@@ -0,0 +1,100 @@
+/// Result of feeding one byte into a [`LineBuffer`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// A complete line with the trailing `\r` (if any) trimmed. Never empty.
+    Complete(&'a str),
+    /// A complete line that was not valid UTF-8; carries its length.
+    NonUtf8(usize),
+    /// The line exceeded the buffer and is dropped, up to and including its
+    /// `\n`; reported once per line.
+    Overflow,
+}
+
+/// Reassembles newline-delimited frames from arbitrarily chunked socket reads.
+pub struct LineBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// Dropping the rest of an overflowed line.
+    discarding: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            discarding: false,
+        }
+    }
+
+    pub fn push(&mut self, b: u8) -> Option<Line<'_>> {
+        if self.discarding {
+            self.discarding = b != b'\n';
+            return None;
+        }
+        if b == b'\n' {
+            let len = core::mem::take(&mut self.len);
+            return match core::str::from_utf8(&self.buf[..len]) {
+                Ok(s) => {
+                    let s = s.trim_end_matches('\r');
+                    (!s.is_empty()).then_some(Line::Complete(s))
+                }
+                Err(_) => Some(Line::NonUtf8(len)),
+            };
+        }
+
+        if self.len < N {
+            self.buf[self.len] = b;
+            self.len += 1;
+            None
+        } else {
+            // overflow; drop the line, and whatever follows up to its end
+            self.len = 0;
+            self.discarding = true;
+            Some(Line::Overflow)
+        }
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lines() {
+        let mut buf = LineBuffer::<16>::new();
+        for &b in b"{\"id\":1" {
+            assert_eq!(buf.push(b), None);
+        }
+        assert_eq!(buf.push(b'\r'), None);
+        assert_eq!(buf.push(b'\n'), Some(Line::Complete("{\"id\":1")));
+        assert_eq!(buf.push(b'\r'), None);
+        assert_eq!(buf.push(b'\n'), None);
+        assert_eq!(buf.push(0xFF), None);
+        assert_eq!(buf.push(b'\n'), Some(Line::NonUtf8(1)));
+    }
+
+    #[test]
+    fn drops_the_rest_of_an_overflowed_line() {
+        let mut buf = LineBuffer::<4>::new();
+        for &b in b"abcd" {
+            assert_eq!(buf.push(b), None);
+        }
+        assert_eq!(buf.push(b'e'), Some(Line::Overflow));
+        // The tail would otherwise come out as a line of its own.
+        for &b in b"fgh" {
+            assert_eq!(buf.push(b), None);
+        }
+        assert_eq!(buf.push(b'\n'), None);
+        for &b in b"ok" {
+            assert_eq!(buf.push(b), None);
+        }
+        assert_eq!(buf.push(b'\n'), Some(Line::Complete("ok")));
+    }
+}
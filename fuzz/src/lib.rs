@@ -0,0 +1,109 @@
+//! Host-side harness around the firmware's untrusted-input paths.
+
+use curtain_protocol::binary::{self, Frame, FrameBuffer};
+use curtain_protocol::line_buffer::{Line, LineBuffer};
+
+// The firmware's codecs have no hardware dependencies; built here so their
+// unit tests run on the host.
+#[path = "../../src/http/request.rs"]
+pub mod http_request;
+#[path = "../../src/mdns/message.rs"]
+pub mod mdns_message;
+#[path = "../../src/mqtt/packet.rs"]
+pub mod mqtt_packet;
+#[path = "../../src/websocket/frame.rs"]
+pub mod websocket_frame;
+
+/// The service type the firmware looks for.
+const SERVICE: &str = "_curtain._tcp.local";
+
+/// Mirrors the `serve` read buffer so overflow behaves as on the device.
+pub const LINE_BUF_LEN: usize = 512;
+
+pub fn parse_command(data: &[u8]) {
+    if let Ok(s) = core::str::from_utf8(data) {
+        check_command(s);
+    }
+}
+
+/// The first byte selects the read chunk size (like `serve`'s 128-byte reads,
+/// but varied), the rest is the TCP byte stream.
+pub fn line_reassembly(data: &[u8]) {
+    let Some((&chunk_len, stream)) = data.split_first() else {
+        return;
+    };
+    let chunk_len = usize::from(chunk_len).max(1);
+
+    let mut line_buf = LineBuffer::<LINE_BUF_LEN>::new();
+    // Whether the current line overflowed; none of it may come out.
+    let mut overflowed = false;
+    for chunk in stream.chunks(chunk_len) {
+        for &b in chunk {
+            match line_buf.push(b) {
+                Some(Line::Complete(s)) => {
+                    assert!(!overflowed);
+                    assert!(!s.is_empty());
+                    assert!(s.len() <= LINE_BUF_LEN);
+                    assert!(!s.contains('\n'));
+                    assert!(!s.ends_with('\r'));
+                    check_command(s);
+                }
+                Some(Line::NonUtf8(len)) => assert!(len <= LINE_BUF_LEN),
+                Some(Line::Overflow) => overflowed = true,
+                None => {}
+            }
+            if b == b'\n' {
+                overflowed = false;
+            }
+        }
+    }
+}
+
+/// Same chunking scheme as [`line_reassembly`], for the postcard framing.
+pub fn frame_reassembly(data: &[u8]) {
+    let Some((&chunk_len, stream)) = data.split_first() else {
+        return;
+    };
+    let chunk_len = usize::from(chunk_len).max(1);
+
+    let mut frame_buf = FrameBuffer::<{ binary::MAX_FRAME_LEN }>::new();
+    for chunk in stream.chunks(chunk_len) {
+        for &b in chunk {
+            match frame_buf.push(b) {
+                Some(Frame::Complete(payload)) => {
+                    assert!(payload.len() <= binary::MAX_FRAME_LEN);
+                    let _ = binary::decode_command(payload);
+                }
+                Some(Frame::Oversized(len)) => assert!(len > binary::MAX_FRAME_LEN),
+                None => {}
+            }
+        }
+    }
+}
+
+/// The bytes of an HTTP request as read into the server's buffer.
+pub fn http_request(data: &[u8]) {
+    if let http_request::Parsed::Complete(request) = http_request::parse(data) {
+        assert!(request.path.starts_with('/'));
+        assert!(!request.path.contains('?'));
+        assert!(request.body.len() < data.len());
+        let _ = request.header("content-length");
+    }
+}
+
+/// A datagram received in answer to the firmware's mDNS query.
+pub fn mdns_response(data: &[u8]) {
+    if let Some(service) = mdns_message::parse_response(data, SERVICE) {
+        assert_ne!(service.ip, [0; 4]);
+    }
+}
+
+fn check_command(s: &str) {
+    if let Some(cmd) = curtain_protocol::parse_command(s) {
+        let _ = curtain_protocol::Command::try_from(&cmd);
+        // Same narrowing `handle_line` does before driving the motor.
+        if let Some(v) = cmd.value.filter(|v| *v <= 100) {
+            let _ = v as u8;
+        }
+    }
+}
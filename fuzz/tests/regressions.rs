@@ -0,0 +1,43 @@
+//! Replays every input under `regressions/<target>/` through its fuzz target.
+//!
+//! Copy crash inputs from `artifacts/<target>/` here once they are fixed.
+
+use std::fs;
+use std::path::Path;
+
+fn replay(target: &str, run: fn(&[u8])) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("regressions")
+        .join(target);
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let data = fs::read(&path).unwrap();
+        println!("{}", path.display());
+        run(&data);
+    }
+}
+
+#[test]
+fn parse_command() {
+    replay("parse_command", curtain_control_fuzz::parse_command);
+}
+
+#[test]
+fn line_reassembly() {
+    replay("line_reassembly", curtain_control_fuzz::line_reassembly);
+}
+
+#[test]
+fn frame_reassembly() {
+    replay("frame_reassembly", curtain_control_fuzz::frame_reassembly);
+}
+
+#[test]
+fn http_request() {
+    replay("http_request", curtain_control_fuzz::http_request);
+}
+
+#[test]
+fn mdns_response() {
+    replay("mdns_response", curtain_control_fuzz::mdns_response);
+}
@@ -7,28 +7,39 @@
 )]
 #![deny(clippy::large_stack_frames)]
 
-use core::cell::RefCell;
-
-use critical_section::Mutex;
-use curtain_control::lineat_motor::LinearMotorController;
+use curtain_control::button::button_task;
+use curtain_control::current::CurrentSense;
+use curtain_control::discovery::discovery_task;
+use curtain_control::encoder::{ENCODER, encoder_task};
+use curtain_control::feedback::{Feedback, FeedbackPot};
+use curtain_control::http::http_server_task;
+use curtain_control::input::{self, input_task};
+use curtain_control::motor_task::motor_task;
+#[cfg(feature = "mqtt")]
+use curtain_control::mqtt::MqttClient;
+#[cfg(not(feature = "mqtt"))]
 use curtain_control::tcp_client::TcpClient;
+use curtain_control::websocket::websocket_task;
+use curtain_control::{MotorController, config};
+use curtain_core::lineat_motor::LinearMotorController;
 use embassy_executor::Spawner;
 use embassy_net::Runner;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Delay, Duration, Timer};
 use esp_backtrace as _;
-use esp_hal::gpio::{Event, Input, InputConfig, Io, Level, OutputConfig};
-use esp_hal::interrupt::{InterruptHandler, Priority};
+use esp_hal::clock::CpuClock;
+use esp_hal::gpio::interconnect::PeripheralOutput;
+use esp_hal::gpio::{DriveMode, Input, InputConfig, Pull};
+use esp_hal::ledc::channel::{self, Channel, ChannelIFace};
+use esp_hal::ledc::timer::{self, TimerIFace};
+use esp_hal::ledc::{LSGlobalClkSource, Ledc, LowSpeed};
+use esp_hal::time::Rate;
 use esp_hal::timer::timg::TimerGroup;
-use esp_hal::{clock::CpuClock, gpio::Output};
-use esp_hal::{handler, ram};
 use esp_radio::wifi::{
     self, ClientConfig, ModeConfig, WifiController, WifiDevice, WifiEvent, WifiStaState,
 };
 use log::{debug, error, info};
 extern crate alloc;
 
-static BUTTON: Mutex<RefCell<Option<Input>>> = Mutex::new(RefCell::new(None));
-
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
@@ -55,21 +66,80 @@ async fn main(spawner: Spawner) -> ! {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
 
-    let step_pin = Output::new(peripherals.GPIO0, Level::High, OutputConfig::default());
-    let direction_pin = Output::new(peripherals.GPIO1, Level::High, OutputConfig::default());
-    let enable_pin = Output::new(peripherals.GPIO2, Level::High, OutputConfig::default());
+    // The C3 has no MCPWM; LEDC drives the H-bridge inputs instead.
+    let mut ledc = Ledc::new(peripherals.LEDC);
+    ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+    let motor_timer = mk_static!(
+        timer::Timer<'static, LowSpeed>,
+        ledc.timer::<LowSpeed>(timer::Number::Timer0)
+    );
+    motor_timer
+        .configure(timer::config::Config {
+            duty: timer::config::Duty::Duty10Bit,
+            clock_source: timer::LSClockSource::APBClk,
+            frequency: Rate::from_khz(config::PWM_FREQUENCY_KHZ),
+        })
+        .expect("Failed to configure the motor PWM timer");
+    // Both channels keep a reference to the timer.
+    let motor_timer: &'static _ = motor_timer;
+    let motor_a = motor_channel(
+        &ledc,
+        motor_timer,
+        channel::Number::Channel0,
+        peripherals.GPIO0,
+    );
+    let motor_b = motor_channel(
+        &ledc,
+        motor_timer,
+        channel::Number::Channel1,
+        peripherals.GPIO1,
+    );
+    let end_stop = Input::new(
+        peripherals.GPIO4,
+        InputConfig::default().with_pull(config::END_STOP_PULL),
+    );
 
-    let mut io = Io::new(peripherals.IO_MUX);
-    io.set_interrupt_handler(handler);
+    let open_end_stop = config::OPEN_END_STOP.then(|| {
+        Input::new(
+            peripherals.GPIO5,
+            InputConfig::default().with_pull(config::END_STOP_PULL),
+        )
+    });
 
-    let mut interrupt_button = Input::new(peripherals.GPIO3, InputConfig::default());
+    let button = Input::new(peripherals.GPIO3, InputConfig::default());
 
-    critical_section::with(|cs| {
-        interrupt_button.listen(Event::FallingEdge);
-        BUTTON.borrow_ref_mut(cs).replace(interrupt_button)
+    // Current sensing and position feedback share GPIO2 and ADC1.
+    let (current_sense, feedback_pot) = if config::CURRENT_SENSE {
+        let current_sense = CurrentSense::new(peripherals.ADC1, peripherals.GPIO2);
+        (Some(current_sense), None)
+    } else if config::POSITION_FEEDBACK {
+        let feedback_pot = FeedbackPot::new(peripherals.ADC1, peripherals.GPIO2);
+        (None, Some(feedback_pot))
+    } else {
+        (None, None)
+    };
+    let encoder_pins = config::ENCODER.then(|| {
+        let pull_up = InputConfig::default().with_pull(Pull::Up);
+        (
+            Input::new(peripherals.GPIO6, pull_up),
+            Input::new(peripherals.GPIO7, pull_up),
+        )
     });
-
-    let stepper_controller = LinearMotorController::new(step_pin, direction_pin, enable_pin);
+    let feedback = match encoder_pins {
+        Some(_) => Some(Feedback::Encoder(ENCODER.sensor())),
+        None => feedback_pot.map(Feedback::Pot),
+    };
+
+    let stepper_controller: MotorController = LinearMotorController::new(
+        input::END_STOP.pin(),
+        config::OPEN_END_STOP.then(|| input::OPEN_END_STOP.pin()),
+        motor_a,
+        motor_b,
+        config::FULL_TRAVEL_MS,
+    )
+    .with_stall_action(config::STALL_ACTION)
+    .with_feedback(feedback, config::FEEDBACK_RANGE)
+    .with_ramps(config::MOTOR_RAMPS, Delay);
 
     esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 66320);
 
@@ -97,18 +167,28 @@ async fn main(spawner: Spawner) -> ! {
         interfaces.sta,
         config,
         mk_static!(
-            embassy_net::StackResources<3>,
-            embassy_net::StackResources::<3>::new()
+            embassy_net::StackResources<{ config::STACK_SOCKETS }>,
+            embassy_net::StackResources::<{ config::STACK_SOCKETS }>::new()
         ),
         seed,
     );
 
     spawner.spawn(connection(wifi_controller)).ok();
     spawner.spawn(net_task(runner)).ok();
-
-    let _rx_buffer: [i32; _] = [0; 4096];
-    let _tx_buffer = [0; 4096];
-    let _cached_value: u8 = 0;
+    spawner.spawn(input_task(end_stop, &input::END_STOP)).ok();
+    if let Some(open_end_stop) = open_end_stop {
+        spawner
+            .spawn(input_task(open_end_stop, &input::OPEN_END_STOP))
+            .ok();
+    }
+    spawner.spawn(input_task(button, &input::BUTTON)).ok();
+    if let Some((a, b)) = encoder_pins {
+        spawner.spawn(encoder_task(a, b)).ok();
+    }
+    spawner
+        .spawn(motor_task(stepper_controller, current_sense))
+        .ok();
+    spawner.spawn(button_task()).ok();
 
     //wait until wifi connected
     loop {
@@ -127,17 +207,50 @@ async fn main(spawner: Spawner) -> ! {
         Timer::after(Duration::from_millis(500)).await;
     }
 
+    let http_rx_buffer = mk_static!(
+        [u8; config::HTTP_RX_BUFFER_SIZE],
+        [0; config::HTTP_RX_BUFFER_SIZE]
+    );
+    let http_tx_buffer = mk_static!(
+        [u8; config::HTTP_TX_BUFFER_SIZE],
+        [0; config::HTTP_TX_BUFFER_SIZE]
+    );
+    spawner
+        .spawn(http_server_task(stack, http_rx_buffer, http_tx_buffer))
+        .ok();
+    let ws_rx_buffer = mk_static!(
+        [u8; config::WS_RX_BUFFER_SIZE],
+        [0; config::WS_RX_BUFFER_SIZE]
+    );
+    let ws_tx_buffer = mk_static!(
+        [u8; config::WS_TX_BUFFER_SIZE],
+        [0; config::WS_TX_BUFFER_SIZE]
+    );
+    spawner
+        .spawn(websocket_task(stack, ws_rx_buffer, ws_tx_buffer))
+        .ok();
+    spawner.spawn(discovery_task(stack)).ok();
+
     // Main client loop: connect, read lines, reconnect on error/close
-    let mut client = TcpClient::new(stepper_controller).await;
+    let rx_buffer = mk_static!(
+        [u8; config::TCP_RX_BUFFER_SIZE],
+        [0; config::TCP_RX_BUFFER_SIZE]
+    );
+    let tx_buffer = mk_static!(
+        [u8; config::TCP_TX_BUFFER_SIZE],
+        [0; config::TCP_TX_BUFFER_SIZE]
+    );
+    #[cfg(not(feature = "mqtt"))]
+    let mut client = TcpClient::new(stack, rx_buffer, tx_buffer).await;
+    #[cfg(feature = "mqtt")]
+    let mut client = MqttClient::new(stack, rx_buffer, tx_buffer).await;
     loop {
         // Small delay to avoid tight reconnect loops
         Timer::after(Duration::from_millis(1_000)).await;
 
-        client.connect(&stack).await;
-
-        // Send register immediately after connect
-
-        client.serve().await;
+        if client.connect().await {
+            client.serve().await;
+        }
 
         // Allow some time before reconnecting
         Timer::after(Duration::from_millis(curtain_control::RECONNECT_DELAY_MS)).await;
@@ -146,6 +259,24 @@ async fn main(spawner: Spawner) -> ! {
     // for inspiration have a look at the examples at https://github.com/esp-rs/esp-hal/tree/esp-hal-v~1.0/examples
 }
 
+/// An H-bridge input driven by `timer`'s PWM, starting out off.
+fn motor_channel(
+    ledc: &Ledc<'static>,
+    timer: &'static timer::Timer<'static, LowSpeed>,
+    number: channel::Number,
+    pin: impl PeripheralOutput<'static>,
+) -> Channel<'static, LowSpeed> {
+    let mut channel = ledc.channel(number, pin);
+    channel
+        .configure(channel::config::Config {
+            timer,
+            duty_pct: 0,
+            drive_mode: DriveMode::PushPull,
+        })
+        .expect("Failed to configure a motor PWM channel");
+    channel
+}
+
 // maintains wifi connection, when it disconnects it tries to reconnect
 #[allow(clippy::large_stack_frames)]
 #[embassy_executor::task]
@@ -185,29 +316,3 @@ async fn connection(mut controller: WifiController<'static>) {
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
     runner.run().await
 }
-
-#[handler]
-#[ram]
-fn handler() {
-    esp_println::println!("GPIO Interrupt");
-
-    if critical_section::with(|cs| {
-        BUTTON
-            .borrow_ref_mut(cs)
-            .as_mut()
-            .unwrap()
-            .is_interrupt_set()
-    }) {
-        esp_println::println!("Button was the source of the interrupt");
-    } else {
-        esp_println::println!("Button was not the source of the interrupt");
-    }
-
-    critical_section::with(|cs| {
-        BUTTON
-            .borrow_ref_mut(cs)
-            .as_mut()
-            .unwrap()
-            .clear_interrupt()
-    });
-}
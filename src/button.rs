@@ -0,0 +1,45 @@
+//! The push button on GPIO3 (active low) as a local control. A short press
+//! toggles the curtain between open and closed, or stops it while it moves;
+//! holding it for [`config::BUTTON_LONG_PRESS_MS`] starts a calibration and
+//! for [`config::BUTTON_VERY_LONG_PRESS_MS`] a factory reset.
+
+use core::cell::Cell;
+
+use curtain_core::input::InputEvent;
+use embassy_time::Duration;
+use log::{debug, info};
+
+use crate::config;
+use crate::input::BUTTON;
+use crate::motor_task::{self, BUTTON_ACTIONS, ButtonAction, MOVING};
+
+/// Turns presses into [`ButtonAction`]s for the motor task, classified by how
+/// long the button was held.
+#[embassy_executor::task]
+pub async fn button_task() {
+    loop {
+        let held = match BUTTON.event().await {
+            InputEvent::Released { held_ms } => Duration::from_millis(held_ms),
+            InputEvent::LongPress => {
+                info!("Button held; release to calibrate, keep holding to reset");
+                continue;
+            }
+            _ => continue,
+        };
+        debug!("Button held for {} ms", held.as_millis());
+
+        let action = if held >= Duration::from_millis(config::BUTTON_VERY_LONG_PRESS_MS) {
+            ButtonAction::FactoryReset
+        } else if held >= Duration::from_millis(config::BUTTON_LONG_PRESS_MS) {
+            ButtonAction::Calibrate
+        } else if MOVING.lock(Cell::get) {
+            // The motor task only reads actions between moves; the stop it
+            // queues merely reports the press.
+            motor_task::interrupt_move();
+            ButtonAction::Stop
+        } else {
+            ButtonAction::Toggle
+        };
+        BUTTON_ACTIONS.send(action).await;
+    }
+}
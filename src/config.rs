@@ -0,0 +1,173 @@
+//! Build-time configuration of the firmware.
+
+use curtain_core::feedback::FeedbackRange;
+use curtain_core::ramp::RampSettings;
+use curtain_core::stall::StallAction;
+use esp_hal::gpio::Pull;
+
+/// Sockets the network stack can hold at once: DHCP, DNS, the server
+/// connection, the HTTP API, the WebSocket endpoint, mDNS discovery and the UDP
+/// discovery responder take one each.
+pub const STACK_SOCKETS: usize = 7;
+
+/// Servers as host name (resolved through the DHCP-provided DNS server, or an
+/// IPv4 literal) and TCP port, in order of preference. On a failed connect the
+/// next one is tried; the last one that worked is kept until it fails.
+pub const SERVERS: &[(&str, u16)] = &[("raspberrypi.fritz.box", 9000)];
+/// mDNS service type browsed for when none of [`SERVERS`] can be reached.
+pub const SERVER_MDNS_SERVICE: &str = "_curtain._tcp.local";
+/// Listening mode: with a port, the device waits for a controller to connect
+/// on it instead of dialling the server, for sites without an always-on server.
+pub const LISTEN_PORT: Option<u16> = None;
+
+/// Events (replies and status pushes) kept for replay when the server resumes
+/// the session after a reconnect; after more than this many during an outage
+/// the resume fails, and the server only gets the current position.
+pub const REPLAY_HISTORY_LEN: usize = 16;
+
+/// Receive buffer of the server connection's TCP socket.
+pub const TCP_RX_BUFFER_SIZE: usize = 4096;
+/// Transmit buffer of the server connection's TCP socket.
+pub const TCP_TX_BUFFER_SIZE: usize = 4096;
+
+/// Secret for authenticating commands with HMAC-SHA256, from the server and
+/// the WebSocket client alike; the HTTP API then refuses to move, though it
+/// still stops a move, and the `mqtt` feature cannot be used. With `None`
+/// commands are accepted unauthenticated. See `curtain_protocol::auth`.
+pub const COMMAND_SECRET: Option<&[u8]> = None;
+
+/// Identity the device presents with [`TLS_PSK`] (feature `tls`).
+pub const TLS_PSK_IDENTITY: &[u8] = b"curtain";
+/// Key shared with the server (feature `tls`), taken from the
+/// `CURTAIN_TLS_PSK` environment variable at build time; give the server the
+/// same bytes. Without it the published placeholder is compiled in, and the
+/// device refuses to open TLS sessions with it.
+pub const TLS_PSK: &[u8] = match option_env!("CURTAIN_TLS_PSK") {
+    Some(key) => key.as_bytes(),
+    None => TLS_PSK_PLACEHOLDER,
+};
+/// The key used when `CURTAIN_TLS_PSK` is not set; anyone can read it here.
+pub const TLS_PSK_PLACEHOLDER: &[u8] = b"change-me-change-me-change-me-32";
+/// Incoming TLS records can be up to 16 KiB plus overhead.
+pub const TLS_READ_RECORD_LEN: usize = 16_640;
+/// Outgoing TLS records; replies are far smaller.
+pub const TLS_WRITE_RECORD_LEN: usize = 4096;
+
+/// Time the actuator takes from closed to fully open; positions are
+/// estimated from how long the motor ran.
+pub const FULL_TRAVEL_MS: u64 = 20_000;
+/// Pull on the limit switch inputs: the end stop, at the closed end, on GPIO4
+/// and the open-end switch on GPIO5. A switch counts as reached while its
+/// input is high, so with the pull-up it is a normally closed switch to
+/// ground; use `Pull::Down` for switches that connect 3.3 V when reached.
+pub const END_STOP_PULL: Pull = Pull::Up;
+/// Whether a second limit switch on GPIO5 marks the open end. Calibration then
+/// measures the travel time instead of trusting [`FULL_TRAVEL_MS`].
+pub const OPEN_END_STOP: bool = false;
+/// Frequency of the PWM on the H-bridge inputs (GPIO0, GPIO1); 20 kHz is
+/// above what the motor makes audible.
+pub const PWM_FREQUENCY_KHZ: u32 = 20;
+/// Speed ramps: moves start and end slowly, and run slowly near the ends of
+/// travel; calibration runs slowly until it has found an end. With `None` the
+/// motor runs at full speed all the way.
+pub const MOTOR_RAMPS: Option<RampSettings> = Some(RampSettings {
+    accel_ms: 500,
+    decel_ms: 500,
+    approach_duty: 40,
+    approach_ms: 1_000,
+    step_ms: 20,
+});
+
+/// Whether a current-sense amplifier on GPIO2 (ADC1) watches the motor for
+/// stalls, e.g. on frozen film. GPIO2 is a strapping pin: the amplifier must
+/// not pull it low while the chip resets.
+pub const CURRENT_SENSE: bool = false;
+/// Time between current readings during a move.
+pub const CURRENT_SAMPLE_INTERVAL_MS: u64 = 10;
+/// Time after the motor starts during which its inrush current is not taken
+/// for a stall.
+pub const INRUSH_BLANKING_MS: u64 = 300;
+/// Current readings, raw from the 12-bit ADC (0..4095 spans about 0..2.5 V),
+/// above which the motor counts as stalled.
+pub const STALL_THRESHOLD: u16 = 2_000;
+/// Readings above [`STALL_THRESHOLD`] in a row that make a stall.
+pub const STALL_SAMPLES: u8 = 5;
+/// Reading that stops the motor at once, even during the inrush blanking.
+pub const OVERCURRENT_THRESHOLD: u16 = 3_500;
+/// What a stalled move does after stopping.
+pub const STALL_ACTION: StallAction = StallAction::Reverse { duration_ms: 500 };
+
+/// Whether the actuator's feedback potentiometer is wired to GPIO2 (ADC1), so
+/// positions are read instead of estimated from [`FULL_TRAVEL_MS`].
+pub const POSITION_FEEDBACK: bool = false;
+/// The potentiometer's raw readings at both ends, if known for this device
+/// (calibration logs them); without, positions are unknown until a
+/// calibration.
+pub const FEEDBACK_RANGE: Option<FeedbackRange> = None;
+/// Whether a two-channel hall encoder on GPIO6 (A) and GPIO7 (B) reports the
+/// position, instead of the potentiometer. Its counts restart with the device,
+/// so positions are unknown until a calibration.
+pub const ENCODER: bool = false;
+/// Time without an encoder count, while the motor runs, that makes a stall.
+pub const ENCODER_STALL_MS: u64 = 300;
+/// Time after the motor starts during which it may spin up without counts.
+pub const ENCODER_BLANKING_MS: u64 = 300;
+/// Missed encoder steps (both channels changing at once) after which the
+/// counts can no longer be trusted and the position is forgotten.
+pub const ENCODER_MAX_MISSED_STEPS: u32 = 8;
+/// Time between position readings during a move.
+pub const FEEDBACK_SAMPLE_INTERVAL_MS: u64 = 10;
+
+const _: () = assert!(
+    !(CURRENT_SENSE && POSITION_FEEDBACK),
+    "current sensing and position feedback both use GPIO2"
+);
+const _: () = assert!(
+    !(ENCODER && POSITION_FEEDBACK),
+    "the encoder and the potentiometer cannot both report the position"
+);
+
+/// Time the button must be quiet before a press or release counts.
+pub const BUTTON_DEBOUNCE_MS: u64 = 20;
+/// Time the end stop must be quiet before its level counts. Zero: a move is
+/// cut on the first edge, and contact bounce only repeats the stop.
+pub const END_STOP_DEBOUNCE_MS: u64 = 0;
+/// Holding the button at least this long starts a calibration.
+pub const BUTTON_LONG_PRESS_MS: u64 = 2_000;
+/// Holding the button at least this long resets the device.
+pub const BUTTON_VERY_LONG_PRESS_MS: u64 = 10_000;
+
+/// Port of the local HTTP API.
+pub const HTTP_PORT: u16 = 80;
+/// Receive buffer of the HTTP API's TCP socket.
+pub const HTTP_RX_BUFFER_SIZE: usize = 1024;
+/// Transmit buffer of the HTTP API's TCP socket.
+pub const HTTP_TX_BUFFER_SIZE: usize = 1024;
+/// Largest request, head and body, the HTTP API accepts; browsers alone send
+/// several hundred bytes of headers.
+pub const HTTP_REQUEST_LEN: usize = 2048;
+
+/// Port of the WebSocket endpoint.
+pub const WS_PORT: u16 = 81;
+/// Receive buffer of the WebSocket endpoint's TCP socket.
+pub const WS_RX_BUFFER_SIZE: usize = 1024;
+/// Transmit buffer of the WebSocket endpoint's TCP socket.
+pub const WS_TX_BUFFER_SIZE: usize = 1024;
+
+/// Seconds between unprompted discovery announcements.
+pub const ANNOUNCE_INTERVAL_SECS: u64 = 60;
+
+/// MQTT broker used instead of the TCP server with the `mqtt` feature.
+pub const MQTT_BROKER_IP_V4: [u8; 4] = [192, 168, 178, 21];
+pub const MQTT_PORT: u16 = 1883;
+pub const MQTT_USERNAME: Option<&str> = None;
+pub const MQTT_PASSWORD: Option<&str> = None;
+pub const MQTT_KEEP_ALIVE_SECS: u16 = 60;
+/// Device topics live under `<prefix>/<uuid>/`.
+pub const MQTT_TOPIC_PREFIX: &str = "curtain";
+/// Home Assistant discovery prefix, including the `cover` component.
+pub const MQTT_DISCOVERY_PREFIX: &str = "homeassistant/cover";
+/// Largest outgoing packet; the discovery config is the biggest one.
+pub const MQTT_TX_PACKET_LEN: usize = 1024;
+/// Largest incoming packet; longer ones are dropped.
+pub const MQTT_RX_PACKET_LEN: usize = 256;
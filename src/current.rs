@@ -0,0 +1,48 @@
+//! Motor current sensing: a shunt amplifier on GPIO2, read through ADC1 while
+//! a move runs and fed to a [`StallDetector`].
+
+use curtain_core::stall::{StallDetector, StallSettings, Trip};
+use embassy_time::{Instant, Timer};
+use esp_hal::Async;
+use esp_hal::analog::adc::{Adc, AdcConfig, AdcPin, Attenuation};
+use esp_hal::peripherals::{ADC1, GPIO2};
+use log::trace;
+
+use crate::config;
+
+const SETTINGS: StallSettings = StallSettings {
+    blanking_ms: config::INRUSH_BLANKING_MS,
+    stall_threshold: config::STALL_THRESHOLD,
+    stall_samples: config::STALL_SAMPLES,
+    overcurrent_threshold: config::OVERCURRENT_THRESHOLD,
+};
+
+pub struct CurrentSense<'a> {
+    adc: Adc<'a, ADC1<'a>, Async>,
+    pin: AdcPin<GPIO2<'a>, ADC1<'a>>,
+}
+
+impl<'a> CurrentSense<'a> {
+    pub fn new(adc: ADC1<'a>, pin: GPIO2<'a>) -> Self {
+        let mut adc_config = AdcConfig::new();
+        let pin = adc_config.enable_pin(pin, Attenuation::_11dB);
+        Self {
+            adc: Adc::new(adc, adc_config).into_async(),
+            pin,
+        }
+    }
+
+    /// Samples the current of the move that began at `started` until it
+    /// stalls; never returns while the motor runs freely.
+    pub async fn watch(&mut self, started: Instant) -> Trip {
+        let mut detector = StallDetector::new(SETTINGS);
+        loop {
+            Timer::after_millis(config::CURRENT_SAMPLE_INTERVAL_MS).await;
+            let reading = self.adc.read_oneshot(&mut self.pin).await;
+            trace!("Motor current: {}", reading);
+            if let Some(trip) = detector.sample(started.elapsed().as_millis(), reading) {
+                return trip;
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Answers UDP discovery broadcasts and announces the device periodically, so
+//! the server and tools can list the curtains that are online. See
+//! [`curtain_protocol::discovery`] for the messages; try it with
+//! `echo '{"type":"discover"}' | socat - UDP-DATAGRAM:255.255.255.255:9001,broadcast`.
+
+use core::fmt::Write as _;
+
+use curtain_protocol::discovery::{Announce, DISCOVERY_PORT, encode_announce, is_discover_request};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{Duration, Instant, with_deadline};
+use heapless::String;
+use log::{debug, error, info};
+
+use crate::motor_task::POSITION;
+use crate::{CLIENT_UUID, config};
+
+const BROADCAST: Ipv4Address = Ipv4Address::new(255, 255, 255, 255);
+
+#[embassy_executor::task]
+pub async fn discovery_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 512];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(DISCOVERY_PORT) {
+        error!("Discovery bind error: {:?}", e);
+        return;
+    }
+    info!("Discovery listening on UDP port {}", DISCOVERY_PORT);
+
+    let interval = Duration::from_secs(config::ANNOUNCE_INTERVAL_SECS);
+    let mut next_announce = Instant::now();
+    let mut request = [0u8; 64];
+    loop {
+        let to = match with_deadline(next_announce, socket.recv_from(&mut request)).await {
+            Ok(Ok((len, meta))) if is_discover_request(&request[..len]) => {
+                debug!("Discovery request from {}", meta.endpoint);
+                meta.endpoint
+            }
+            Ok(_) => continue,
+            Err(_) => {
+                next_announce += interval;
+                IpEndpoint::new(BROADCAST.into(), DISCOVERY_PORT)
+            }
+        };
+        announce(stack, &socket, to).await;
+    }
+}
+
+async fn announce(stack: Stack<'_>, socket: &UdpSocket<'_>, to: IpEndpoint) {
+    let Some(config) = stack.config_v4() else {
+        return;
+    };
+    let mut ip = String::<15>::new();
+    let _ = write!(ip, "{}", config.address.address());
+
+    let announce = Announce {
+        uuid: CLIENT_UUID,
+        ip: &ip,
+        version: env!("CARGO_PKG_VERSION"),
+        value: POSITION.lock(|position| position.get()),
+    };
+    let mut datagram = [0u8; 256];
+    let Some(len) = encode_announce(&announce, &mut datagram) else {
+        error!("Encode error ({:?})", announce);
+        return;
+    };
+    if let Err(e) = socket.send_to(&datagram[..len], to).await {
+        error!("Discovery send error: {:?}", e);
+    }
+}
@@ -0,0 +1,107 @@
+//! A two-channel hall encoder on GPIO6 (A) and GPIO7 (B). The ESP32-C3 has no
+//! pulse counter peripheral, so [`encoder_task`] decodes the edges in software;
+//! actuator encoders pulse at a few kHz at most, which GPIO interrupts keep up
+//! with.
+
+use core::cell::Cell;
+
+use curtain_core::encoder::Quadrature;
+use curtain_core::feedback::PositionSensor;
+use curtain_core::stall::Trip;
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::gpio::Input;
+use log::debug;
+
+use crate::config;
+
+pub static ENCODER: Encoder = Encoder::new();
+
+pub struct Encoder {
+    count: Mutex<CriticalSectionRawMutex, Cell<i32>>,
+    /// Steps missed since start-up or the last [`Encoder::reset_missed`].
+    missed: Mutex<CriticalSectionRawMutex, Cell<u32>>,
+    /// Signalled on every count.
+    pulse: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Encoder {
+    const fn new() -> Self {
+        Self {
+            count: Mutex::new(Cell::new(0)),
+            missed: Mutex::new(Cell::new(0)),
+            pulse: Signal::new(),
+        }
+    }
+
+    /// Counts since start-up; only differences mean anything.
+    pub fn count(&self) -> i32 {
+        self.count.lock(Cell::get)
+    }
+
+    /// Steps missed since the counts were last referenced; each leaves the
+    /// count off by up to two.
+    pub fn missed(&self) -> u32 {
+        self.missed.lock(Cell::get)
+    }
+
+    /// To be called when the counts are referenced anew, by a calibration.
+    pub fn reset_missed(&self) {
+        self.missed.lock(|missed| missed.set(0));
+    }
+
+    /// Returns once [`config::ENCODER_STALL_MS`] pass without a count while
+    /// the motor runs: the actuator is not moving. The first
+    /// [`config::ENCODER_BLANKING_MS`] after `started` are spin-up.
+    pub async fn stalled(&self, started: Instant) -> Trip {
+        Timer::at(started + Duration::from_millis(config::ENCODER_BLANKING_MS)).await;
+        self.pulse.reset();
+        let timeout = || Timer::after_millis(config::ENCODER_STALL_MS);
+        while let Either::First(()) = select(self.pulse.wait(), timeout()).await {}
+        Trip::Stall
+    }
+
+    /// The counts as the motor controller's position sensor.
+    pub fn sensor(&'static self) -> EncoderSensor {
+        EncoderSensor(self)
+    }
+}
+
+pub struct EncoderSensor(&'static Encoder);
+
+impl PositionSensor for EncoderSensor {
+    async fn sample(&mut self) -> i32 {
+        Timer::after_millis(config::FEEDBACK_SAMPLE_INTERVAL_MS).await;
+        self.0.count()
+    }
+
+    fn is_incremental(&self) -> bool {
+        true
+    }
+}
+
+#[embassy_executor::task]
+pub async fn encoder_task(mut a: Input<'static>, mut b: Input<'static>) {
+    let mut decoder = Quadrature::new(a.is_high(), b.is_high());
+    loop {
+        select(a.wait_for_any_edge(), b.wait_for_any_edge()).await;
+        match decoder.update(a.is_high(), b.is_high()) {
+            Some(0) => {}
+            Some(step) => {
+                ENCODER
+                    .count
+                    .lock(|count| count.set(count.get().wrapping_add(i32::from(step))));
+                ENCODER.pulse.signal(());
+            }
+            None => {
+                debug!("Encoder missed a step");
+                ENCODER
+                    .missed
+                    .lock(|missed| missed.set(missed.get().saturating_add(1)));
+            }
+        }
+    }
+}
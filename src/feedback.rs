@@ -0,0 +1,59 @@
+//! The motor controller's [`PositionSensor`]: the actuator's feedback
+//! potentiometer on GPIO2, read through ADC1, or the hall encoder.
+
+use curtain_core::feedback::PositionSensor;
+use embassy_time::Timer;
+use esp_hal::Async;
+use esp_hal::analog::adc::{Adc, AdcConfig, AdcPin, Attenuation};
+use esp_hal::peripherals::{ADC1, GPIO2};
+use log::trace;
+
+use crate::config;
+use crate::encoder::EncoderSensor;
+
+/// The position sensor the board is configured with.
+pub enum Feedback<'a> {
+    Pot(FeedbackPot<'a>),
+    Encoder(EncoderSensor),
+}
+
+impl PositionSensor for Feedback<'_> {
+    async fn sample(&mut self) -> i32 {
+        match self {
+            Self::Pot(pot) => pot.sample().await,
+            Self::Encoder(encoder) => encoder.sample().await,
+        }
+    }
+
+    fn is_incremental(&self) -> bool {
+        match self {
+            Self::Pot(pot) => pot.is_incremental(),
+            Self::Encoder(encoder) => encoder.is_incremental(),
+        }
+    }
+}
+
+pub struct FeedbackPot<'a> {
+    adc: Adc<'a, ADC1<'a>, Async>,
+    pin: AdcPin<GPIO2<'a>, ADC1<'a>>,
+}
+
+impl<'a> FeedbackPot<'a> {
+    pub fn new(adc: ADC1<'a>, pin: GPIO2<'a>) -> Self {
+        let mut adc_config = AdcConfig::new();
+        let pin = adc_config.enable_pin(pin, Attenuation::_11dB);
+        Self {
+            adc: Adc::new(adc, adc_config).into_async(),
+            pin,
+        }
+    }
+}
+
+impl PositionSensor for FeedbackPot<'_> {
+    async fn sample(&mut self) -> i32 {
+        Timer::after_millis(config::FEEDBACK_SAMPLE_INTERVAL_MS).await;
+        let reading = self.adc.read_oneshot(&mut self.pin).await;
+        trace!("Position feedback: {}", reading);
+        i32::from(reading)
+    }
+}
@@ -0,0 +1,257 @@
+//! Local HTTP API, so a single curtain can be driven from a laptop without the
+//! server, e.g. during installation:
+//!
+//! - `GET /state`: `{"type":"status","value":40}`, `null` while uncalibrated;
+//!   the position as of the last finished move
+//! - `POST /position` with `{"value":40}`, 100 being fully open
+//! - `POST /calibrate`
+//! - `POST /stop`: ends the move in progress
+//! - `GET /diagnostics`: `{"server":"192.168.178.2:9000"}`, the server the
+//!   device is connected to, `null` while disconnected
+//!
+//! Moves take seconds and the server handles one connection at a time, so the
+//! POSTs answer with an `ack` as soon as the motor task has the command, or an
+//! `error` and status 409 if the motor cannot take it, or 503 if it is busy
+//! with another command queued; follow the move with `GET /state`. For
+//! example `curl -d '{"value":40}' http://<device-ip>/position`.
+//!
+//! The POSTs cannot be authenticated, so with [`config::COMMAND_SECRET`] set
+//! they are refused with status 403; the device then only moves on
+//! authenticated commands from the server or the WebSocket endpoint.
+//!
+//! `GET /` serves a control page built on these endpoints, for installers
+//! with nothing but a browser.
+
+pub mod request;
+
+use core::cell::Cell;
+use core::fmt::Write as _;
+
+use curtain_protocol::{Command, Reply, encode_reply};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpEndpoint, Stack};
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_io_async::Write;
+use heapless::String;
+use log::{debug, error, info};
+use serde::Deserialize;
+
+use self::request::{Parsed, Request};
+use crate::config;
+use crate::motor_task::{self, LOCAL_COMMANDS, POSITION};
+use crate::tcp_client::ACTIVE_SERVER;
+
+/// Time a client gets to send its complete request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time a command may wait for room in [`LOCAL_COMMANDS`]; it is full while a
+/// move runs with another command queued behind it.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest JSON response body; each is a single [`Reply`].
+const RESPONSE_BODY_LEN: usize = 128;
+
+/// The control page, stored in flash.
+const INDEX_HTML: &str = include_str!("index.html");
+
+#[derive(Deserialize)]
+struct SetPosition {
+    value: u32,
+}
+
+enum Body {
+    Reply(Reply<'static>),
+    Html(&'static str),
+    Diagnostics { server: Option<IpEndpoint> },
+}
+
+struct Response {
+    status: &'static str,
+    body: Body,
+}
+
+impl Response {
+    fn ok(reply: Reply<'static>) -> Self {
+        Self {
+            status: "200 OK",
+            body: Body::Reply(reply),
+        }
+    }
+
+    fn error(status: &'static str, id: u32, message: &'static str) -> Self {
+        Self {
+            status,
+            body: Body::Reply(Reply::Error { id, message }),
+        }
+    }
+}
+
+/// Serves one connection at a time on [`config::HTTP_PORT`]; moves are
+/// forwarded to the motor task through [`LOCAL_COMMANDS`].
+#[embassy_executor::task]
+pub async fn http_server_task(
+    stack: Stack<'static>,
+    rx_buffer: &'static mut [u8],
+    tx_buffer: &'static mut [u8],
+) {
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+    let mut request_buf = [0u8; config::HTTP_REQUEST_LEN];
+    let mut next_id: u32 = 1;
+
+    info!("HTTP API listening on port {}", config::HTTP_PORT);
+    loop {
+        if let Err(e) = socket.accept(config::HTTP_PORT).await {
+            error!("HTTP accept error: {:?}", e);
+            Timer::after(Duration::from_millis(crate::RECONNECT_DELAY_MS)).await;
+            continue;
+        }
+        debug!("HTTP connection from {:?}", socket.remote_endpoint());
+
+        if let Ok(Some(len)) =
+            with_timeout(REQUEST_TIMEOUT, read_request(&mut socket, &mut request_buf)).await
+        {
+            let id = next_id;
+            next_id = next_id.wrapping_add(1);
+            let response = match request::parse(&request_buf[..len]) {
+                Parsed::Complete(request) => handle(&request, id).await,
+                Parsed::Incomplete => Response::error("413 Content Too Large", id, "too large"),
+                Parsed::Invalid => Response::error("400 Bad Request", id, "bad request"),
+            };
+            if let Err(e) = respond(&mut socket, &response).await {
+                error!("HTTP write error: {:?}", e);
+            }
+        }
+
+        shutdown(&mut socket).await;
+    }
+}
+
+/// Gives whatever was written a moment to drain, then frees the socket for the
+/// next accept.
+pub(crate) async fn shutdown(socket: &mut TcpSocket<'_>) {
+    socket.close();
+    let _ = with_timeout(Duration::from_secs(1), socket.flush()).await;
+    socket.abort();
+    let _ = socket.flush().await;
+}
+
+/// Reads until `buf` holds a complete or invalid request, or is full. Returns
+/// `None` if the client goes away first.
+pub(crate) async fn read_request(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match socket.read(&mut buf[len..]).await {
+            Ok(0) => return None,
+            Ok(n) => len += n,
+            Err(e) => {
+                error!("HTTP read error: {:?}", e);
+                return None;
+            }
+        }
+        if request::parse(&buf[..len]) != Parsed::Incomplete {
+            break;
+        }
+    }
+    Some(len)
+}
+
+async fn handle(request: &Request<'_>, id: u32) -> Response {
+    debug!("HTTP {} {}", request.method, request.path);
+    let command = match (request.method, request.path) {
+        ("GET", "/") => {
+            return Response {
+                status: "200 OK",
+                body: Body::Html(INDEX_HTML),
+            };
+        }
+        ("GET", "/diagnostics") => {
+            return Response {
+                status: "200 OK",
+                body: Body::Diagnostics {
+                    server: ACTIVE_SERVER.lock(Cell::get),
+                },
+            };
+        }
+        // Stopping stays open to anyone on the network: it is the safety
+        // action, and it cannot move the actuator.
+        ("POST", "/position" | "/calibrate") if config::COMMAND_SECRET.is_some() => {
+            return Response::error("403 Forbidden", id, "commands need authentication");
+        }
+        ("GET", "/state") => {
+            return Response::ok(Reply::Status {
+                value: POSITION.lock(Cell::get),
+            });
+        }
+        ("POST", "/position") => match serde_json_core::from_slice::<SetPosition>(request.body) {
+            Ok((body, _)) if body.value <= 100 => {
+                if POSITION.lock(Cell::get).is_none() {
+                    return Response::error("409 Conflict", id, "not calibrated");
+                }
+                Command::SetValue {
+                    id,
+                    value: body.value,
+                }
+            }
+            _ => return Response::error("400 Bad Request", id, "expected {\"value\":0..100}"),
+        },
+        ("POST", "/calibrate") => Command::Calibrate { id },
+        // Never queued: the motor task only reads commands between moves.
+        ("POST", "/stop") => {
+            motor_task::interrupt_move();
+            return Response::ok(Reply::Ack { id, ok: true });
+        }
+        (_, "/" | "/state" | "/position" | "/calibrate" | "/stop" | "/diagnostics") => {
+            return Response::error("405 Method Not Allowed", id, "method not allowed");
+        }
+        _ => return Response::error("404 Not Found", id, "not found"),
+    };
+
+    let queued = with_timeout(
+        QUEUE_TIMEOUT,
+        motor_task::submit(LOCAL_COMMANDS.dyn_sender(), command),
+    )
+    .await;
+    match queued {
+        Ok(()) => Response::ok(Reply::Ack { id, ok: true }),
+        Err(_) => Response::error("503 Service Unavailable", id, "busy"),
+    }
+}
+
+async fn respond(
+    socket: &mut TcpSocket<'_>,
+    response: &Response,
+) -> Result<(), embassy_net::tcp::Error> {
+    let mut json = [0u8; RESPONSE_BODY_LEN];
+    let mut text = String::<RESPONSE_BODY_LEN>::new();
+    let (content_type, body) = match &response.body {
+        Body::Reply(reply) => {
+            let len = encode_reply(reply, &mut json).unwrap_or_else(|| {
+                error!("Encode error ({:?})", reply);
+                0
+            });
+            ("application/json", &json[..len])
+        }
+        Body::Html(html) => ("text/html; charset=utf-8", html.as_bytes()),
+        Body::Diagnostics { server } => {
+            // Fits: an endpoint is at most 21 characters.
+            let _ = match server {
+                Some(server) => writeln!(text, "{{\"server\":\"{}\"}}", server),
+                None => writeln!(text, "{{\"server\":null}}"),
+            };
+            ("application/json", text.as_bytes())
+        }
+    };
+
+    let mut head = String::<160>::new();
+    // Fits: the longest status line and content type plus a five digit length.
+    let _ = write!(
+        head,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        content_type,
+        body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body).await?;
+    socket.flush().await
+}
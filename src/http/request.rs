@@ -0,0 +1,158 @@
+//! Just enough HTTP/1.1 request parsing for the device's API: the request line,
+//! the headers as raw text and the body.
+
+const HEAD_END: &[u8] = b"\r\n\r\n";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// Request target without the query string.
+    pub path: &'a str,
+    /// Header lines, unparsed; see [`Request::header`].
+    pub headers: &'a str,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// Value of the first header called `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        header(self.headers, name)
+    }
+}
+
+fn header<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.split("\r\n").find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Result of parsing the bytes received so far.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Parsed<'a> {
+    Complete(Request<'a>),
+    /// The head or the announced body has not fully arrived yet.
+    Incomplete,
+    Invalid,
+}
+
+pub fn parse(buf: &[u8]) -> Parsed<'_> {
+    let Some(head_len) = buf
+        .windows(HEAD_END.len())
+        .position(|w| w == HEAD_END)
+        .map(|i| i + HEAD_END.len())
+    else {
+        return Parsed::Incomplete;
+    };
+    let Ok(head) = core::str::from_utf8(&buf[..head_len]) else {
+        return Parsed::Invalid;
+    };
+
+    let (request_line, headers) = head.split_once("\r\n").unwrap_or_default();
+    let mut request_line = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Parsed::Invalid;
+    };
+    if method.is_empty() || !target.starts_with('/') || !version.starts_with("HTTP/1.") {
+        return Parsed::Invalid;
+    }
+    let path = target.split('?').next().unwrap_or(target);
+
+    let content_length = match header(headers, "content-length").map(str::parse) {
+        None => 0,
+        Some(Ok(len)) => len,
+        Some(Err(_)) => return Parsed::Invalid,
+    };
+
+    match buf[head_len..].get(..content_length) {
+        Some(body) => Parsed::Complete(Request {
+            method,
+            path,
+            headers,
+            body,
+        }),
+        None => Parsed::Incomplete,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let raw = b"POST /position?src=page HTTP/1.1\r\n\
+            Host: 192.168.178.40\r\n\
+            content-length: 12\r\n\
+            \r\n\
+            {\"value\":40}";
+        let Parsed::Complete(request) = parse(raw) else {
+            panic!("{:?}", parse(raw));
+        };
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/position");
+        assert_eq!(request.header("Host"), Some("192.168.178.40"));
+        assert_eq!(request.header("Content-Length"), Some("12"));
+        assert_eq!(request.header("Upgrade"), None);
+        assert_eq!(request.body, b"{\"value\":40}");
+    }
+
+    #[test]
+    fn parses_requests_without_headers() {
+        assert_eq!(
+            parse(b"GET / HTTP/1.0\r\n\r\n"),
+            Parsed::Complete(Request {
+                method: "GET",
+                path: "/",
+                headers: "\r\n",
+                body: b"",
+            })
+        );
+    }
+
+    #[test]
+    fn waits_for_the_end_of_the_head() {
+        let raw = b"GET /state HTTP/1.1\r\nHost: curtain\r\n\r\n";
+        for len in 0..raw.len() {
+            assert_eq!(parse(&raw[..len]), Parsed::Incomplete, "{}", len);
+        }
+        assert!(matches!(parse(raw), Parsed::Complete(_)));
+    }
+
+    #[test]
+    fn stays_incomplete_on_over_long_headers() {
+        // The server answers 413 once its buffer fills up without a head.
+        let mut raw = [b'a'; 4096];
+        raw[..24].copy_from_slice(b"GET / HTTP/1.1\r\nCookie: ");
+        assert_eq!(parse(&raw), Parsed::Incomplete);
+    }
+
+    #[test]
+    fn waits_for_the_announced_body() {
+        let raw = b"POST /position HTTP/1.1\r\nContent-Length: 4096\r\n\r\n{\"value\":40}";
+        assert_eq!(parse(raw), Parsed::Incomplete);
+        let raw = b"POST /position HTTP/1.1\r\nContent-Length: 99999999999999999999\r\n\r\n";
+        assert_eq!(parse(raw), Parsed::Invalid);
+        let raw = b"POST /position HTTP/1.1\r\nContent-Length: -1\r\n\r\n";
+        assert_eq!(parse(raw), Parsed::Invalid);
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        for raw in [
+            &b"GET /\r\n\r\n"[..],
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b" / HTTP/1.1\r\n\r\n",
+            b"GET state HTTP/1.1\r\n\r\n",
+            b"GET / SPDY/3\r\n\r\n",
+            b"GET /\xff HTTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(parse(raw), Parsed::Invalid, "{:?}", raw);
+        }
+    }
+}
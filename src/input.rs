@@ -0,0 +1,134 @@
+//! Debounced inputs. Each pin is owned by an [`input_task`] that waits for its
+//! edges and runs them through a [`Debouncer`]; the debounced level can be read
+//! at any time, and the resulting events are queued on the input's channel.
+
+use core::cell::Cell;
+use core::convert::Infallible;
+
+use curtain_core::input::{Debouncer, InputEvent, InputSettings};
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+use embedded_hal::digital::{ErrorType, InputPin};
+use esp_hal::gpio::Input;
+use log::trace;
+
+use crate::config;
+
+/// The push button on GPIO3, active low.
+pub static BUTTON: DebouncedInput = DebouncedInput::new(InputSettings {
+    debounce_ms: config::BUTTON_DEBOUNCE_MS,
+    active_high: false,
+    long_press_ms: Some(config::BUTTON_LONG_PRESS_MS),
+});
+
+/// The end stop on GPIO4; high once the actuator has reached it.
+pub static END_STOP: DebouncedInput = DebouncedInput::new(InputSettings {
+    debounce_ms: config::END_STOP_DEBOUNCE_MS,
+    active_high: true,
+    long_press_ms: None,
+});
+
+/// The optional limit switch at the open end on GPIO5, wired like
+/// [`END_STOP`]; see [`config::OPEN_END_STOP`].
+pub static OPEN_END_STOP: DebouncedInput = DebouncedInput::new(InputSettings {
+    debounce_ms: config::END_STOP_DEBOUNCE_MS,
+    active_high: true,
+    long_press_ms: None,
+});
+
+pub struct DebouncedInput {
+    settings: InputSettings,
+    high: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+    events: Channel<CriticalSectionRawMutex, InputEvent, 4>,
+}
+
+impl DebouncedInput {
+    const fn new(settings: InputSettings) -> Self {
+        Self {
+            settings,
+            // Released until its task has read the pin.
+            high: Mutex::new(Cell::new(!settings.active_high)),
+            events: Channel::new(),
+        }
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high.lock(Cell::get)
+    }
+
+    /// Waits for the next event; events are dropped while nobody waits and
+    /// the queue is full.
+    pub async fn event(&self) -> InputEvent {
+        self.events.receive().await
+    }
+
+    /// Drops the events nobody has waited for yet.
+    pub fn clear(&self) {
+        self.events.clear();
+    }
+
+    /// The debounced level as an `embedded-hal` pin, for code written
+    /// against one.
+    pub fn pin(&'static self) -> DebouncedPin {
+        DebouncedPin(self)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct DebouncedPin(&'static DebouncedInput);
+
+impl ErrorType for DebouncedPin {
+    type Error = Infallible;
+}
+
+impl InputPin for DebouncedPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.0.is_high())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.0.is_high())
+    }
+}
+
+/// Watches `pin` and publishes its debounced level and events to `input`.
+#[embassy_executor::task(pool_size = 3)]
+pub async fn input_task(mut pin: Input<'static>, input: &'static DebouncedInput) {
+    let mut debouncer = Debouncer::new(input.settings, pin.is_high(), now_ms());
+    input.high.lock(|high| high.set(debouncer.is_high()));
+
+    loop {
+        let edge = match debouncer.deadline() {
+            Some(deadline) => {
+                let deadline = Timer::at(Instant::from_millis(deadline));
+                matches!(
+                    select(pin.wait_for_any_edge(), deadline).await,
+                    Either::First(())
+                )
+            }
+            None => {
+                pin.wait_for_any_edge().await;
+                true
+            }
+        };
+        let now = now_ms();
+        if edge {
+            debouncer.on_edge(now);
+        }
+        for event in debouncer.poll(pin.is_high(), now).into_iter().flatten() {
+            if let InputEvent::Edge { high: level } = event {
+                input.high.lock(|high| high.set(level));
+            }
+            if input.events.try_send(event).is_err() {
+                trace!("Input event queue full; dropping {:?}", event);
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    Instant::now().as_millis()
+}
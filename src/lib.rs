@@ -1,7 +1,40 @@
 #![no_std]
 
-pub(crate) mod error;
+pub mod button;
+pub mod config;
+pub mod current;
+pub mod discovery;
+pub mod encoder;
+pub mod feedback;
+pub mod http;
+pub mod input;
+pub mod mdns;
+pub mod motor_task;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod tcp_client;
-pub mod lineat_motor;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod websocket;
+
+use curtain_core::lineat_motor::LinearMotorController;
+use embassy_time::Delay;
+use esp_hal::ledc::LowSpeed;
+use esp_hal::ledc::channel::Channel;
+
+use crate::feedback::Feedback;
+use crate::input::DebouncedPin;
+
 pub const RECONNECT_DELAY_MS: u64 = 2_000;
 const CLIENT_UUID: &str = "8a3a3b0e-10b0-4f5e-bb14-7eac9ced0001";
+
+/// The motor controller wired to the board's (debounced) limit switches,
+/// H-bridge PWM channels and, if configured, position sensor.
+pub type MotorController<'a> = LinearMotorController<
+    DebouncedPin,
+    DebouncedPin,
+    Channel<'a, LowSpeed>,
+    Channel<'a, LowSpeed>,
+    Feedback<'a>,
+    Delay,
+>;
@@ -0,0 +1,294 @@
+//! The two DNS messages mDNS service discovery needs: a one-shot `PTR` query
+//! for a service type, and the `SRV` and `A` records of the answers. No
+//! hardware dependencies.
+
+use heapless::String;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// Asks responders to answer unicast, to the query's source port.
+const UNICAST_RESPONSE: u16 = 0x8000;
+const FLAG_RESPONSE: u16 = 0x8000;
+
+const HEADER_LEN: usize = 12;
+
+/// Longest name DNS allows, in its dotted form.
+const MAX_NAME_LEN: usize = 255;
+
+/// Compression pointers followed per name before assuming a loop.
+const MAX_POINTERS: usize = 16;
+
+type Name = String<MAX_NAME_LEN>;
+
+/// Address and port of a discovered service instance.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Service {
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+/// Writes a `PTR` query for `service` (e.g. `_curtain._tcp.local`) into `buf`,
+/// returning its length.
+pub fn encode_query(service: &str, buf: &mut [u8]) -> Option<usize> {
+    let mut writer = Writer { buf, pos: 0 };
+    // id 0, no flags, one question
+    writer.bytes(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+    for label in service.split('.') {
+        let len = u8::try_from(label.len())
+            .ok()
+            .filter(|len| (1..64).contains(len))?;
+        writer.bytes(&[len])?;
+        writer.bytes(label.as_bytes())?;
+    }
+    writer.bytes(&[0])?;
+    writer.bytes(&TYPE_PTR.to_be_bytes())?;
+    writer.bytes(&(CLASS_IN | UNICAST_RESPONSE).to_be_bytes())?;
+    Some(writer.pos)
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + bytes.len())?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Some(())
+    }
+}
+
+/// Finds an instance of `service` in a response: the first `SRV` record for
+/// it, and the `A` record of the host that record points to.
+pub fn parse_response(msg: &[u8], service: &str) -> Option<Service> {
+    let (host, port) = records(msg)?.find_map(|record| {
+        if record.rtype != TYPE_SRV || !is_instance_of(&record.name, service) {
+            return None;
+        }
+        let port = record.rdata.get(4..6)?;
+        let mut host = Name::new();
+        read_name(msg, record.rdata_pos + 6, &mut host)?;
+        Some((host, u16::from_be_bytes([port[0], port[1]])))
+    })?;
+    let ip = records(msg)?.find_map(|record| {
+        let ip = <[u8; 4]>::try_from(record.rdata).ok()?;
+        (record.rtype == TYPE_A && record.name.eq_ignore_ascii_case(&host)).then_some(ip)
+    })?;
+    Some(Service { ip, port })
+}
+
+struct Record<'a> {
+    name: Name,
+    rtype: u16,
+    rdata: &'a [u8],
+    /// Offset of `rdata` in the message, for names compressed against it.
+    rdata_pos: usize,
+}
+
+/// The resource records of a response, in all sections.
+fn records(msg: &[u8]) -> Option<Records<'_>> {
+    let header = msg.get(..HEADER_LEN)?;
+    let field = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+    if field(2) & FLAG_RESPONSE == 0 {
+        return None;
+    }
+    let mut pos = HEADER_LEN;
+    for _ in 0..field(4) {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    let remaining = usize::from(field(6)) + usize::from(field(8)) + usize::from(field(10));
+    Some(Records {
+        msg,
+        pos,
+        remaining,
+    })
+}
+
+struct Records<'a> {
+    msg: &'a [u8],
+    pos: usize,
+    remaining: usize,
+}
+
+impl<'a> Records<'a> {
+    fn read(&mut self) -> Option<Record<'a>> {
+        let mut name = Name::new();
+        let pos = read_name(self.msg, self.pos, &mut name)?;
+        let fixed = self.msg.get(pos..pos + 10)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let rdata_len = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+        let rdata_pos = pos + 10;
+        let rdata = self.msg.get(rdata_pos..rdata_pos + rdata_len)?;
+        self.pos = rdata_pos + rdata_len;
+        Some(Record {
+            name,
+            rtype,
+            rdata,
+            rdata_pos,
+        })
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let record = self.read();
+        if record.is_none() {
+            // Malformed; nothing after this point can be trusted.
+            self.remaining = 0;
+        }
+        record
+    }
+}
+
+/// Whether `name` is `<instance>.<service>`.
+fn is_instance_of(name: &str, service: &str) -> bool {
+    name.len() > service.len() + 1
+        && name.as_bytes()[name.len() - service.len() - 1] == b'.'
+        && name[name.len() - service.len()..].eq_ignore_ascii_case(service)
+}
+
+/// Returns the position after the name at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xC0 == 0xC0 => return Some(pos + 2),
+            l => pos += 1 + usize::from(l),
+        }
+    }
+}
+
+/// Decodes the possibly compressed name at `pos` into `out` as dotted text and
+/// returns the position after it.
+fn read_name(msg: &[u8], mut pos: usize, out: &mut Name) -> Option<usize> {
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => return Some(end.unwrap_or(pos + 1)),
+            l if l & 0xC0 == 0xC0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                let offset = usize::from(u16::from_be_bytes([l & 0x3F, *msg.get(pos + 1)?]));
+                end.get_or_insert(pos + 2);
+                pos = offset;
+            }
+            l => {
+                let label = msg.get(pos + 1..pos + 1 + usize::from(l))?;
+                if !out.is_empty() {
+                    out.push('.').ok()?;
+                }
+                out.push_str(core::str::from_utf8(label).ok()?).ok()?;
+                pos += 1 + usize::from(l);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICE: &str = "_curtain._tcp.local";
+
+    /// An answer to a query for [`SERVICE`], laid out as avahi-daemon sends
+    /// it: the `PTR` answer, then `SRV`, `TXT` and `A` records, with names
+    /// compressed against the question's.
+    const RESPONSE: &[u8] = b"\
+        \x00\x00\x84\x00\x00\x00\x00\x01\x00\x00\x00\x03\x08\x5f\x63\x75\
+        \x72\x74\x61\x69\x6e\x04\x5f\x74\x63\x70\x05\x6c\x6f\x63\x61\x6c\
+        \x00\x00\x0c\x00\x01\x00\x00\x11\x94\x00\x11\x0e\x63\x75\x72\x74\
+        \x61\x69\x6e\x2d\x73\x65\x72\x76\x65\x72\xc0\x0c\xc0\x2b\x00\x21\
+        \x80\x01\x00\x00\x00\x78\x00\x16\x00\x00\x00\x00\x23\x28\x0d\x67\
+        \x72\x65\x65\x6e\x68\x6f\x75\x73\x65\x2d\x70\x69\xc0\x1a\xc0\x2b\
+        \x00\x10\x80\x01\x00\x00\x11\x94\x00\x01\x00\xc0\x4e\x00\x01\x80\
+        \x01\x00\x00\x00\x78\x00\x04\xc0\xa8\xb2\x02";
+
+    #[test]
+    fn encodes_ptr_query() {
+        let mut buf = [0u8; 64];
+        let len = encode_query(SERVICE, &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+              \x08_curtain\x04_tcp\x05local\x00\x00\x0c\x80\x01"
+        );
+        assert_eq!(encode_query(SERVICE, &mut buf[..len - 1]), None);
+        assert_eq!(encode_query("_curtain..local", &mut buf), None);
+    }
+
+    #[test]
+    fn finds_the_service_in_a_response() {
+        assert_eq!(
+            parse_response(RESPONSE, SERVICE),
+            Some(Service {
+                ip: [192, 168, 178, 2],
+                port: 9000,
+            })
+        );
+        assert_eq!(
+            parse_response(RESPONSE, "_CURTAIN._tcp.local"),
+            parse_response(RESPONSE, SERVICE)
+        );
+        assert_eq!(parse_response(RESPONSE, "_http._tcp.local"), None);
+    }
+
+    #[test]
+    fn follows_compression_pointers() {
+        // The SRV record's target, `greenhouse-pi` and a pointer to `local`.
+        let mut host = Name::new();
+        assert_eq!(read_name(RESPONSE, 0x4e, &mut host), Some(0x5e));
+        assert_eq!(host, "greenhouse-pi.local");
+        // The A record's name: one pointer to the above.
+        let mut host = Name::new();
+        assert_eq!(read_name(RESPONSE, 0x6b, &mut host), Some(0x6d));
+        assert_eq!(host, "greenhouse-pi.local");
+    }
+
+    #[test]
+    fn gives_up_on_pointer_loops() {
+        // Each pointer leads to the one before it, down to a name at 12.
+        let mut msg = [0u8; HEADER_LEN + 3 + 2 * (MAX_POINTERS + 1)];
+        msg[HEADER_LEN..HEADER_LEN + 3].copy_from_slice(b"\x01a\x00");
+        let mut target = HEADER_LEN;
+        for pointer in msg[HEADER_LEN + 3..].as_chunks_mut::<2>().0 {
+            pointer.copy_from_slice(&(0xC000 | target as u16).to_be_bytes());
+            target += if target == HEADER_LEN { 3 } else { 2 };
+        }
+        let last = msg.len() - 2;
+        let mut name = Name::new();
+        assert!(read_name(&msg, last - 2, &mut name).is_some());
+        assert_eq!(name, "a");
+        assert_eq!(read_name(&msg, last, &mut Name::new()), None);
+
+        // A name pointing at itself.
+        let looped = b"\x00\x00\x84\x00\x00\x00\x00\x01\x00\x00\x00\x00\xc0\x0c";
+        assert_eq!(read_name(looped, HEADER_LEN, &mut Name::new()), None);
+        assert_eq!(parse_response(looped, SERVICE), None);
+    }
+
+    #[test]
+    fn rejects_queries_and_truncated_responses() {
+        let mut query = [0u8; 64];
+        let len = encode_query(SERVICE, &mut query).unwrap();
+        assert_eq!(parse_response(&query[..len], SERVICE), None);
+        for len in 0..RESPONSE.len() {
+            assert_eq!(parse_response(&RESPONSE[..len], SERVICE), None, "{}", len);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! mDNS service discovery (RFC 6762/6763), used to find the server when its
+//! hostname does not resolve. Announce it on the Pi with e.g.
+//! `avahi-publish -s curtain-server _curtain._tcp 9000`.
+
+pub mod message;
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{Duration, Instant, with_deadline};
+use log::{debug, error, info};
+
+const MDNS_GROUP: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+/// Time to wait for answers after each query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const QUERY_ATTEMPTS: usize = 3;
+
+/// Largest response read; mDNS messages stay within a single Ethernet frame.
+const MAX_MESSAGE_LEN: usize = 1500;
+
+/// Asks the local network for an instance of `service` (e.g.
+/// `_curtain._tcp.local`) and returns the first one that answers.
+pub async fn discover(stack: Stack<'_>, service: &str) -> Option<IpEndpoint> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; MAX_MESSAGE_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; 128];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // A query from a port other than 5353 is a one-shot query; responders
+    // answer it unicast, so there is no multicast group to join.
+    if let Err(e) = socket.bind(0) {
+        error!("mDNS bind error: {:?}", e);
+        return None;
+    }
+
+    let mut query = [0u8; 128];
+    let query_len = message::encode_query(service, &mut query)?;
+    let mut response = [0u8; MAX_MESSAGE_LEN];
+    for _ in 0..QUERY_ATTEMPTS {
+        debug!("mDNS query for {}", service);
+        if let Err(e) = socket
+            .send_to(&query[..query_len], (MDNS_GROUP, MDNS_PORT))
+            .await
+        {
+            error!("mDNS send error: {:?}", e);
+            return None;
+        }
+
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        while let Ok(received) = with_deadline(deadline, socket.recv_from(&mut response)).await {
+            let Ok((len, meta)) = received else {
+                continue;
+            };
+            if let Some(found) = message::parse_response(&response[..len], service) {
+                let endpoint = IpEndpoint::new(IpAddress::Ipv4(found.ip.into()), found.port);
+                info!("mDNS: {} at {} (from {})", service, endpoint, meta.endpoint);
+                return Some(endpoint);
+            }
+        }
+    }
+    None
+}
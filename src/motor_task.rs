@@ -0,0 +1,371 @@
+use core::cell::Cell;
+
+use curtain_core::Error;
+use curtain_core::input::InputEvent;
+use curtain_core::lineat_motor::{Direction, Move, MoveEnd};
+use curtain_protocol::{Command, Reply};
+use embassy_futures::select::{Either, Either4, select, select4};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::{Channel, DynamicSender};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use log::{debug, info, warn};
+
+use crate::MotorController;
+use crate::config;
+use crate::current::CurrentSense;
+use crate::encoder::ENCODER;
+use crate::input::{END_STOP, OPEN_END_STOP};
+#[cfg(not(feature = "mqtt"))]
+use crate::tcp_client;
+
+/// Commands decoded by the connection's reader, executed in order.
+pub static COMMANDS: Channel<CriticalSectionRawMutex, Command, 4> = Channel::new();
+
+/// Frames for the MQTT client's writer: replies to commands as well as
+/// unsolicited status pushes. The TCP client keeps its events in a log
+/// instead; see [`crate::tcp_client::record_event`].
+#[cfg(feature = "mqtt")]
+pub static REPLIES: Channel<CriticalSectionRawMutex, Reply<'static>, 8> = Channel::new();
+
+/// Signalled whenever a move or calibration succeeds, so the MQTT client
+/// clears the fault it last published.
+#[cfg(feature = "mqtt")]
+pub static FAULT_CLEARED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Commands from the device's own HTTP API. They get no reply: the API answers
+/// once a command is queued, and clients follow the move through
+/// [`POSITION`]. Status pushes still go to the server so it sees local moves.
+pub static LOCAL_COMMANDS: Channel<CriticalSectionRawMutex, Command, 1> = Channel::new();
+
+/// Commands from the WebSocket client, answered on [`WS_REPLIES`].
+pub static WS_COMMANDS: Channel<CriticalSectionRawMutex, Command, 1> = Channel::new();
+
+/// Frames for the WebSocket client, including status pushes.
+pub static WS_REPLIES: Channel<CriticalSectionRawMutex, Reply<'static>, 8> = Channel::new();
+
+/// Actions from the device's button; their outcome is reported to the server
+/// and the WebSocket client as `local` events.
+pub static BUTTON_ACTIONS: Channel<CriticalSectionRawMutex, ButtonAction, 1> = Channel::new();
+
+/// Whether a move or a calibration is in progress, so the actuator may be
+/// moving.
+pub static MOVING: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Cuts the move in progress short; see [`submit`].
+static STOP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Last known position, for readers that only need a snapshot; `None` while
+/// uncalibrated.
+pub static POSITION: Mutex<CriticalSectionRawMutex, Cell<Option<u8>>> = Mutex::new(Cell::new(None));
+
+/// Queues `command` for the motor task. The motor task only reads commands
+/// between moves, so a stop also interrupts the move in progress.
+pub async fn submit(commands: DynamicSender<'_, Command>, command: Command) {
+    if let Command::Stop { .. } = command {
+        interrupt_move();
+    }
+    commands.send(command).await;
+}
+
+/// Ends the move in progress, if any, as if a stop had been executed.
+pub fn interrupt_move() {
+    if MOVING.lock(Cell::get) {
+        STOP.signal(());
+    }
+}
+
+/// Where the reply to a command goes.
+enum ReplyTo {
+    Server,
+    WebSocket,
+    /// The HTTP API, which does not wait for one.
+    Nobody,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ButtonAction {
+    /// Opens a closed (or half-open) curtain fully, closes an open one.
+    Toggle,
+    Stop,
+    Calibrate,
+    /// Forgets the calibration and restarts the device.
+    FactoryReset,
+}
+
+/// Owns the motor controller so a slow move never blocks the network tasks.
+/// Moves are watched for stalls if there is a `current` sense.
+#[embassy_executor::task]
+pub async fn motor_task(
+    mut controller: MotorController<'static>,
+    mut current: Option<CurrentSense<'static>>,
+) {
+    // With a known feedback range the position is there from the start.
+    controller.sync_position().await;
+    report_position(&controller);
+    loop {
+        let (command, replies) = match select4(
+            COMMANDS.receive(),
+            LOCAL_COMMANDS.receive(),
+            WS_COMMANDS.receive(),
+            BUTTON_ACTIONS.receive(),
+        )
+        .await
+        {
+            Either4::First(command) => (command, ReplyTo::Server),
+            Either4::Second(command) => (command, ReplyTo::Nobody),
+            Either4::Third(command) => (command, ReplyTo::WebSocket),
+            Either4::Fourth(action) => {
+                button_action(&mut controller, &mut current, action).await;
+                continue;
+            }
+        };
+        let (reply, moved) = execute(&mut controller, &mut current, command).await;
+        match replies {
+            ReplyTo::Server => to_server(reply),
+            ReplyTo::WebSocket => WS_REPLIES.send(reply).await,
+            ReplyTo::Nobody => debug!("Local command done: {:?}", reply),
+        }
+        if moved {
+            report_position(&controller);
+        }
+    }
+}
+
+async fn button_action(
+    controller: &mut MotorController<'static>,
+    current: &mut Option<CurrentSense<'static>>,
+    action: ButtonAction,
+) {
+    info!("button: {:?}", action);
+    // Button actions have no command id; the events carry none either.
+    let (name, command) = match action {
+        ButtonAction::Toggle => {
+            let value = match controller.get_state() {
+                Some(value) if value >= 50 => 0,
+                _ => 100,
+            };
+            ("toggle", Command::SetValue { id: 0, value })
+        }
+        ButtonAction::Stop => ("stop", Command::Stop { id: 0 }),
+        ButtonAction::Calibrate => ("calibrate", Command::Calibrate { id: 0 }),
+        ButtonAction::FactoryReset => {
+            controller.stop();
+            push(Reply::Local {
+                action: "factory_reset",
+                error: None,
+            });
+            // Calibration is all the state there is, and it lives in RAM; a
+            // restart resets it. Give the event a moment to go out first.
+            Timer::after(Duration::from_secs(1)).await;
+            esp_hal::system::software_reset();
+        }
+    };
+    let (reply, moved) = execute(controller, current, command).await;
+    let error = match reply {
+        Reply::Error { message, .. } => Some(message),
+        _ => None,
+    };
+    push(Reply::Local {
+        action: name,
+        error,
+    });
+    if moved {
+        report_position(controller);
+    }
+}
+
+/// Runs one command; also returns whether the position may have changed.
+async fn execute(
+    controller: &mut MotorController<'static>,
+    current: &mut Option<CurrentSense<'static>>,
+    command: Command,
+) -> (Reply<'static>, bool) {
+    let wait = async |motion| wait_for_move(motion, current.as_mut()).await;
+    let (reply, moved) = match command {
+        Command::SetValue { id, value } if value <= 100 => {
+            info!("set_value id={} value={}", id, value);
+            let result = while_moving(controller.set_state(value as u8, wait)).await;
+            report_fault(&result);
+            (to_reply(id, result), true)
+        }
+        Command::SetValue { id, .. } => (
+            Reply::Error {
+                id,
+                message: "value out of range 0..100",
+            },
+            false,
+        ),
+        Command::GetValue { id } => {
+            controller.sync_position().await;
+            info!("get_value id={} -> {:?}", id, controller.get_state());
+            let reply = match controller.get_state() {
+                Some(value) => Reply::Value { id, value },
+                None => error_reply(id, Error::NotCalibrated),
+            };
+            (reply, false)
+        }
+        Command::Calibrate { id } => {
+            info!("calibrate start (id={})", id);
+            // Counts from before are about to be re-referenced.
+            ENCODER.reset_missed();
+            let result = while_moving(controller.calibrate(wait)).await;
+            report_fault(&result);
+            info!(
+                "calibrate done (id={}), full travel {} ms, feedback range {:?}",
+                id,
+                controller.full_travel_ms(),
+                controller.feedback_range()
+            );
+            (to_reply(id, result), true)
+        }
+        Command::Stop { id } => {
+            info!("stop (id={})", id);
+            controller.stop();
+            (Reply::Ack { id, ok: true }, false)
+        }
+    };
+
+    if config::ENCODER
+        && controller.get_state().is_some()
+        && ENCODER.missed() > config::ENCODER_MAX_MISSED_STEPS
+    {
+        warn!("Encoder missed too many steps; forgetting the position");
+        controller.forget_position();
+        push(Reply::Fault {
+            error: "encoder missed steps",
+        });
+        return (reply, true);
+    }
+    (reply, moved)
+}
+
+/// Runs `motion`, a move or calibration, with [`MOVING`] set; other commands
+/// leave it clear, so a button press during them toggles rather than stops.
+async fn while_moving<T>(motion: impl Future<Output = T>) -> T {
+    MOVING.lock(|moving| moving.set(true));
+    let result = motion.await;
+    MOVING.lock(|moving| moving.set(false));
+    result
+}
+
+/// Times a move for the controller, ending it early when the limit switch in
+/// its direction trips, the motor stalls or a stop is requested.
+async fn wait_for_move(motion: Move, current: Option<&mut CurrentSense<'static>>) -> MoveEnd {
+    // Without an open-end switch its input has no task, and no events.
+    let limit = match motion.direction {
+        Direction::Opening => &OPEN_END_STOP,
+        Direction::Closing => &END_STOP,
+    };
+    // Events from before the move say nothing about this one.
+    limit.clear();
+    STOP.reset();
+    let started = Instant::now();
+    let end_stop = async { while limit.event().await != InputEvent::Pressed {} };
+    let current_stall = async {
+        match current {
+            Some(current) => current.watch(started).await,
+            None => core::future::pending().await,
+        }
+    };
+    let encoder_stall = async {
+        if !config::ENCODER {
+            return core::future::pending().await;
+        }
+        ENCODER.stalled(started).await
+    };
+    let stall = async {
+        match select(current_stall, encoder_stall).await {
+            Either::First(trip) | Either::Second(trip) => trip,
+        }
+    };
+    let end = select4(
+        Timer::after(Duration::from_millis(motion.duration_ms)),
+        end_stop,
+        STOP.wait(),
+        stall,
+    )
+    .await;
+    let elapsed_ms = started.elapsed().as_millis();
+    match end {
+        Either4::First(()) => MoveEnd::Completed,
+        Either4::Second(()) => {
+            warn!(
+                "Limit switch tripped after {} ms of {:?}",
+                elapsed_ms, motion
+            );
+            MoveEnd::EndStop { elapsed_ms }
+        }
+        Either4::Third(()) => MoveEnd::Stopped { elapsed_ms },
+        Either4::Fourth(trip) => {
+            warn!("{:?} after {} ms of {:?}", trip, elapsed_ms, motion);
+            MoveEnd::Stalled { elapsed_ms }
+        }
+    }
+}
+
+fn report_position(controller: &MotorController<'static>) {
+    let value = controller.get_state();
+    POSITION.lock(|position| position.set(value));
+    push(Reply::Status { value });
+}
+
+/// Sends an event to the server and the WebSocket client. Nobody drains the
+/// WebSocket queue while the client is away; a command from elsewhere must not
+/// block on it.
+fn push(event: Reply<'static>) {
+    if WS_REPLIES.try_send(event.clone()).is_err() {
+        debug!("WebSocket queue full; dropping {:?}", event);
+    }
+    to_server(event);
+}
+
+/// Sends an event to the server. The TCP client numbers and keeps it even
+/// while disconnected, for the next session to replay; the MQTT client
+/// publishes the current state on connect instead, so what does not fit its
+/// queue meanwhile is dropped.
+fn to_server(event: Reply<'static>) {
+    #[cfg(not(feature = "mqtt"))]
+    tcp_client::record_event(event);
+    #[cfg(feature = "mqtt")]
+    if REPLIES.try_send(event).is_err() {
+        debug!("Reply queue full; dropping an event");
+    }
+}
+
+/// Tells the server and the WebSocket client about faults of a move, whoever
+/// asked for it; a successful move ends the fault for MQTT.
+fn report_fault(result: &curtain_core::Result<()>) {
+    match result {
+        Err(e @ (Error::Stalled | Error::LimitSwitchFault)) => push(Reply::Fault {
+            error: error_message(e),
+        }),
+        #[cfg(feature = "mqtt")]
+        Ok(()) => FAULT_CLEARED.signal(()),
+        _ => {}
+    }
+}
+
+fn to_reply(id: u32, result: curtain_core::Result<()>) -> Reply<'static> {
+    match result {
+        Ok(()) => Reply::Ack { id, ok: true },
+        Err(e) => error_reply(id, e),
+    }
+}
+
+fn error_reply(id: u32, e: Error) -> Reply<'static> {
+    Reply::Error {
+        id,
+        message: error_message(&e),
+    }
+}
+
+fn error_message(e: &Error) -> &'static str {
+    match e {
+        Error::NotCalibrated => "not calibrated",
+        Error::CalibrationFailed => "calibration failed",
+        Error::LimitSwitchFault => "both limit switches active",
+        Error::Stalled => "motor stalled",
+    }
+}
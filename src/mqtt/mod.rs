@@ -0,0 +1,408 @@
+//! MQTT 3.1.1 transport with Home Assistant `cover` discovery.
+//!
+//! Enabled with the `mqtt` feature in place of [`TcpClient`](crate::tcp_client::TcpClient).
+//! The device publishes under `<MQTT_TOPIC_PREFIX>/<uuid>/`:
+//!
+//! - `availability`: `online`/`offline` (retained, `offline` is the last will)
+//! - `position`: `0`..`100`, 100 being fully open, or `None` while unknown
+//!   (retained)
+//! - `fault`: the last motor fault, e.g. `motor stalled`; emptied by the next
+//!   successful move or calibration (retained)
+//!
+//! and listens on:
+//!
+//! - `set`: `OPEN`, `CLOSE` or `STOP`
+//! - `set_position`: `0`..`100`
+//! - `calibrate`: any payload
+//!
+//! To try it against a local broker, run `mosquitto -v`, point
+//! `MQTT_BROKER_IP_V4` at it and watch with `mosquitto_sub -v -t 'curtain/#'`;
+//! `mosquitto_pub -t curtain/<uuid>/set_position -m 40` moves the curtain.
+
+pub mod packet;
+
+use core::cell::Cell;
+use core::fmt::Write as _;
+
+use curtain_protocol::{Command, Reply};
+use embassy_futures::select::{Either3, select, select3};
+use embassy_net::Stack;
+use embassy_net::tcp::{State, TcpReader, TcpSocket, TcpWriter};
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_io_async::Write;
+use heapless::String;
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+
+use self::packet::{Connect, Packet, PacketBuffer, Received, Will};
+use crate::motor_task::{self, COMMANDS, FAULT_CLEARED, POSITION, REPLIES};
+use crate::{CLIENT_UUID, RECONNECT_DELAY_MS, config};
+
+// MQTT commands carry no MAC, and the broker's credentials are no substitute.
+const _: () = assert!(
+    config::COMMAND_SECRET.is_none(),
+    "the mqtt feature cannot authenticate commands; unset COMMAND_SECRET"
+);
+
+const ONLINE: &[u8] = b"online";
+const OFFLINE: &[u8] = b"offline";
+/// Position payload while uncalibrated; Home Assistant shows it as unknown.
+const UNKNOWN: &[u8] = b"None";
+/// Payload on the `set` topic that stops the curtain; Home Assistant's default.
+const PAYLOAD_STOP: &str = "STOP";
+
+/// Topic names derived from the device UUID, built once at start-up.
+struct Topics {
+    availability: String<64>,
+    position: String<64>,
+    fault: String<64>,
+    set: String<64>,
+    set_position: String<64>,
+    calibrate: String<64>,
+    discovery: String<96>,
+}
+
+impl Topics {
+    fn new() -> Self {
+        fn topic<const N: usize>(prefix: &str, name: &str) -> String<N> {
+            let mut topic = String::new();
+            write!(topic, "{}/{}/{}", prefix, CLIENT_UUID, name)
+                .expect("MQTT topic prefix too long");
+            topic
+        }
+
+        Self {
+            availability: topic(config::MQTT_TOPIC_PREFIX, "availability"),
+            position: topic(config::MQTT_TOPIC_PREFIX, "position"),
+            fault: topic(config::MQTT_TOPIC_PREFIX, "fault"),
+            set: topic(config::MQTT_TOPIC_PREFIX, "set"),
+            set_position: topic(config::MQTT_TOPIC_PREFIX, "set_position"),
+            calibrate: topic(config::MQTT_TOPIC_PREFIX, "calibrate"),
+            discovery: topic(config::MQTT_DISCOVERY_PREFIX, "config"),
+        }
+    }
+
+    /// Maps a publish on one of our command topics to a motor command.
+    fn command(&self, topic: &str, payload: &[u8], id: u32) -> Option<Command> {
+        if topic == self.set.as_str() {
+            match payload {
+                b"OPEN" => Some(Command::SetValue { id, value: 100 }),
+                b"CLOSE" => Some(Command::SetValue { id, value: 0 }),
+                // Submitted like any stop, so it ends the move in progress.
+                _ if payload == PAYLOAD_STOP.as_bytes() => Some(Command::Stop { id }),
+                _ => {
+                    warn!("Unsupported cover command {:?}", payload);
+                    None
+                }
+            }
+        } else if topic == self.set_position.as_str() {
+            let value = core::str::from_utf8(payload).ok()?.trim().parse().ok()?;
+            Some(Command::SetValue { id, value })
+        } else if topic == self.calibrate.as_str() {
+            Some(Command::Calibrate { id })
+        } else {
+            None
+        }
+    }
+}
+
+/// Home Assistant MQTT discovery config for a `cover` entity.
+#[derive(Serialize)]
+struct CoverDiscovery<'a> {
+    name: &'a str,
+    unique_id: &'a str,
+    device_class: &'a str,
+    availability_topic: &'a str,
+    command_topic: &'a str,
+    position_topic: &'a str,
+    set_position_topic: &'a str,
+    payload_stop: &'a str,
+    device: DiscoveryDevice<'a>,
+}
+
+#[derive(Serialize)]
+struct DiscoveryDevice<'a> {
+    identifiers: [&'a str; 1],
+    name: &'a str,
+    manufacturer: &'a str,
+    sw_version: &'a str,
+}
+
+/// Connection to the MQTT broker; same role as [`TcpClient`](crate::tcp_client::TcpClient).
+pub struct MqttClient<'a> {
+    socket: TcpSocket<'a>,
+    topics: Topics,
+    tx_packet: [u8; config::MQTT_TX_PACKET_LEN],
+}
+
+impl<'a> MqttClient<'a> {
+    pub async fn new(stack: Stack<'a>, rx_buffer: &'a mut [u8], tx_buffer: &'a mut [u8]) -> Self {
+        let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+        socket.set_timeout(None);
+        Self {
+            socket,
+            topics: Topics::new(),
+            tx_packet: [0; config::MQTT_TX_PACKET_LEN],
+        }
+    }
+
+    /// Opens the TCP connection and the MQTT session, then announces the
+    /// device. Returns whether the session is up.
+    pub async fn connect(&mut self) -> bool {
+        if self.socket.state() != State::Closed {
+            self.socket.abort();
+            let _ = self.socket.flush().await;
+        }
+
+        let address = embassy_net::IpAddress::Ipv4(config::MQTT_BROKER_IP_V4.into());
+        info!(
+            "Connecting to MQTT broker {}:{} ...",
+            address,
+            config::MQTT_PORT
+        );
+        if let Err(e) = self.socket.connect((address, config::MQTT_PORT)).await {
+            error!("Connect error: {:?}", e);
+            Timer::after(Duration::from_millis(RECONNECT_DELAY_MS)).await;
+            return false;
+        }
+
+        let connected = self.open_session().await.is_some();
+        if !connected {
+            self.socket.abort();
+        }
+        connected
+    }
+
+    async fn open_session(&mut self) -> Option<()> {
+        let connect = Connect {
+            client_id: CLIENT_UUID,
+            keep_alive_secs: config::MQTT_KEEP_ALIVE_SECS,
+            will: Some(Will {
+                topic: &self.topics.availability,
+                payload: OFFLINE,
+                retain: true,
+            }),
+            username: config::MQTT_USERNAME,
+            password: config::MQTT_PASSWORD,
+        };
+        let len = packet::encode_connect(&connect, &mut self.tx_packet)?;
+        write_packet(&mut self.socket, &self.tx_packet[..len]).await?;
+
+        match with_timeout(Duration::from_secs(10), read_connack(&mut self.socket)).await {
+            Ok(Some(0)) => info!("MQTT session open"),
+            Ok(Some(code)) => {
+                error!("Broker refused connection (return code {})", code);
+                return None;
+            }
+            Ok(None) => return None,
+            Err(_) => {
+                error!("No CONNACK from broker");
+                return None;
+            }
+        }
+
+        let topics = [
+            self.topics.set.as_str(),
+            self.topics.set_position.as_str(),
+            self.topics.calibrate.as_str(),
+        ];
+        let len = packet::encode_subscribe(1, &topics, &mut self.tx_packet)?;
+        write_packet(&mut self.socket, &self.tx_packet[..len]).await?;
+
+        let discovery = CoverDiscovery {
+            name: "Curtain",
+            unique_id: CLIENT_UUID,
+            device_class: "curtain",
+            availability_topic: &self.topics.availability,
+            command_topic: &self.topics.set,
+            position_topic: &self.topics.position,
+            set_position_topic: &self.topics.set_position,
+            payload_stop: PAYLOAD_STOP,
+            device: DiscoveryDevice {
+                identifiers: [CLIENT_UUID],
+                name: "Greenhouse curtain",
+                manufacturer: "OpenGreenhouseManager",
+                sw_version: env!("CARGO_PKG_VERSION"),
+            },
+        };
+        let len =
+            packet::encode_publish(&self.topics.discovery, true, &mut self.tx_packet, |buf| {
+                serde_json_core::to_slice(&discovery, buf).ok()
+            })?;
+        write_packet(&mut self.socket, &self.tx_packet[..len]).await?;
+
+        let len = packet::encode_publish(
+            &self.topics.availability,
+            true,
+            &mut self.tx_packet,
+            |buf| {
+                let payload = buf.get_mut(..ONLINE.len())?;
+                payload.copy_from_slice(ONLINE);
+                Some(ONLINE.len())
+            },
+        )?;
+        write_packet(&mut self.socket, &self.tx_packet[..len]).await?;
+
+        // What was queued while disconnected is stale; the broker gets the
+        // position as it is now. The motor task may be busy with a move, so
+        // it is not asked.
+        REPLIES.clear();
+        let status = Reply::Status {
+            value: POSITION.lock(Cell::get),
+        };
+        if let Some(len) = publish_reply(&status, &self.topics, &mut self.tx_packet) {
+            write_packet(&mut self.socket, &self.tx_packet[..len]).await?;
+        }
+        Some(())
+    }
+
+    /// Runs the session until the broker closes it or an I/O error occurs,
+    /// with the socket halves running as two concurrent futures.
+    pub async fn serve(&mut self) {
+        let (mut reader, mut writer) = self.socket.split();
+        select(
+            read_loop(&mut reader, &self.topics),
+            write_loop(&mut writer, &mut self.tx_packet, &self.topics),
+        )
+        .await;
+    }
+}
+
+async fn read_connack(socket: &mut TcpSocket<'_>) -> Option<u8> {
+    let mut packet_buf = PacketBuffer::<8>::new();
+    let mut chunk = [0u8; 8];
+    loop {
+        let n = match socket.read(&mut chunk).await {
+            Ok(0) => {
+                info!("Broker closed connection");
+                return None;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                error!("Read error: {:?}", e);
+                return None;
+            }
+        };
+        for &b in &chunk[..n] {
+            match packet_buf.push(b) {
+                Some(Received::Packet(Packet::ConnAck { return_code })) => {
+                    return Some(return_code);
+                }
+                Some(other) => {
+                    error!("Expected CONNACK, got {:?}", other);
+                    return None;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+async fn read_loop(reader: &mut TcpReader<'_>, topics: &Topics) {
+    let mut packet_buf = PacketBuffer::<{ config::MQTT_RX_PACKET_LEN }>::new();
+    let mut chunk = [0u8; 128];
+    let mut next_id: u32 = 1;
+
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) => {
+                info!("Broker closed connection");
+                return;
+            }
+            Ok(n) => {
+                trace!("RX chunk ({} bytes): {:02X?}", n, &chunk[..n]);
+                for &b in &chunk[..n] {
+                    match packet_buf.push(b) {
+                        Some(Received::Packet(Packet::Publish { topic, payload })) => {
+                            debug!("RX publish {}: {:?}", topic, payload);
+                            if let Some(cmd) = topics.command(topic, payload, next_id) {
+                                next_id = next_id.wrapping_add(1);
+                                motor_task::submit(COMMANDS.dyn_sender(), cmd).await;
+                            }
+                        }
+                        Some(Received::Packet(packet)) => {
+                            trace!("RX {:?}", packet);
+                        }
+                        Some(Received::Oversized(len)) => {
+                            error!("Packet too long ({} bytes); dropping", len);
+                        }
+                        None => {}
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Read error: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn write_loop(writer: &mut TcpWriter<'_>, tx_packet: &mut [u8], topics: &Topics) {
+    // Any packet resets the broker's keep-alive timer; ping when idle.
+    let ping_interval = Duration::from_secs(u64::from(config::MQTT_KEEP_ALIVE_SECS) / 2);
+    loop {
+        // Replies first: a fault still queued must not outlive the move that
+        // cleared it.
+        let packet = match select3(
+            REPLIES.receive(),
+            FAULT_CLEARED.wait(),
+            Timer::after(ping_interval),
+        )
+        .await
+        {
+            Either3::First(reply) => publish_reply(&reply, topics, tx_packet),
+            // An empty retained message deletes the retained fault.
+            Either3::Second(()) => encode_retained(&topics.fault, &[], tx_packet),
+            Either3::Third(()) => packet::encode_pingreq(tx_packet),
+        };
+        let Some(len) = packet else {
+            continue;
+        };
+        if write_packet(writer, &tx_packet[..len]).await.is_none() {
+            return;
+        }
+    }
+}
+
+/// Encodes the publish, if any, that reports `reply` to the broker.
+fn publish_reply(reply: &Reply, topics: &Topics, tx_packet: &mut [u8]) -> Option<usize> {
+    match *reply {
+        Reply::Status { value: Some(value) } | Reply::Value { value, .. } => {
+            let mut payload = String::<3>::new();
+            let _ = write!(payload, "{}", value);
+            encode_retained(&topics.position, payload.as_bytes(), tx_packet)
+        }
+        // Calibration failed, or the position was forgotten.
+        Reply::Status { value: None } => encode_retained(&topics.position, UNKNOWN, tx_packet),
+        Reply::Error { id, message } => {
+            warn!("Command {} failed: {}", id, message);
+            None
+        }
+        Reply::Fault { error } => {
+            warn!("Motor fault: {}", error);
+            encode_retained(&topics.fault, error.as_bytes(), tx_packet)
+        }
+        _ => None,
+    }
+}
+
+/// Encodes a retained publish of `payload` on `topic`.
+fn encode_retained(topic: &str, payload: &[u8], tx_packet: &mut [u8]) -> Option<usize> {
+    packet::encode_publish(topic, true, tx_packet, |buf| {
+        let dst = buf.get_mut(..payload.len())?;
+        dst.copy_from_slice(payload);
+        Some(payload.len())
+    })
+}
+
+async fn write_packet(
+    writer: &mut impl Write<Error = embassy_net::tcp::Error>,
+    packet: &[u8],
+) -> Option<()> {
+    writer
+        .write_all(packet)
+        .await
+        .inspect_err(|e| error!("Write error: {:?}", e))
+        .ok()
+}
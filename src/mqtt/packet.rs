@@ -0,0 +1,455 @@
+//! The subset of MQTT 3.1.1 the curtain needs: QoS 0 publish/subscribe, a last
+//! will and keep-alive pings. Encoders write complete packets into a caller
+//! buffer and return their length, or `None` if it does not fit.
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82; // reserved flags 0b0010
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+
+const RETAIN: u8 = 0x01;
+
+const PROTOCOL_LEVEL: u8 = 4; // 3.1.1
+const FLAG_USERNAME: u8 = 0x80;
+const FLAG_PASSWORD: u8 = 0x40;
+const FLAG_WILL_RETAIN: u8 = 0x20;
+const FLAG_WILL: u8 = 0x04;
+const FLAG_CLEAN_SESSION: u8 = 0x02;
+
+/// Room reserved in front of the body for the fixed header, which is only
+/// known once the body has been written.
+const MAX_HEADER_LEN: usize = 5;
+
+/// Published by the broker on our behalf if the connection drops.
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub keep_alive_secs: u16,
+    pub will: Option<Will<'a>>,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+}
+
+pub fn encode_connect(connect: &Connect, buf: &mut [u8]) -> Option<usize> {
+    let mut flags = FLAG_CLEAN_SESSION;
+    if let Some(will) = &connect.will {
+        flags |= FLAG_WILL;
+        if will.retain {
+            flags |= FLAG_WILL_RETAIN;
+        }
+    }
+    if connect.username.is_some() {
+        flags |= FLAG_USERNAME;
+    }
+    if connect.password.is_some() {
+        flags |= FLAG_PASSWORD;
+    }
+
+    let mut body = Body::new(buf)?;
+    body.str("MQTT")?;
+    body.u8(PROTOCOL_LEVEL)?;
+    body.u8(flags)?;
+    body.u16(connect.keep_alive_secs)?;
+    body.str(connect.client_id)?;
+    if let Some(will) = &connect.will {
+        body.str(will.topic)?;
+        body.u16(u16::try_from(will.payload.len()).ok()?)?;
+        body.bytes(will.payload)?;
+    }
+    if let Some(username) = connect.username {
+        body.str(username)?;
+    }
+    if let Some(password) = connect.password {
+        body.str(password)?;
+    }
+    body.finish(CONNECT)
+}
+
+/// Encodes a QoS 0 publish whose payload is written in place by `payload`,
+/// which returns the payload length.
+pub fn encode_publish(
+    topic: &str,
+    retain: bool,
+    buf: &mut [u8],
+    payload: impl FnOnce(&mut [u8]) -> Option<usize>,
+) -> Option<usize> {
+    let mut body = Body::new(buf)?;
+    body.str(topic)?;
+    let len = payload(body.remaining())?;
+    body.advance(len)?;
+    body.finish(if retain { PUBLISH | RETAIN } else { PUBLISH })
+}
+
+/// Subscribes to each of `topics` at QoS 0.
+pub fn encode_subscribe(packet_id: u16, topics: &[&str], buf: &mut [u8]) -> Option<usize> {
+    let mut body = Body::new(buf)?;
+    body.u16(packet_id)?;
+    for topic in topics {
+        body.str(topic)?;
+        body.u8(0)?;
+    }
+    body.finish(SUBSCRIBE)
+}
+
+pub fn encode_pingreq(buf: &mut [u8]) -> Option<usize> {
+    Body::new(buf)?.finish(PINGREQ)
+}
+
+/// Writes a packet body after [`MAX_HEADER_LEN`] reserved bytes.
+struct Body<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Body<'a> {
+    fn new(buf: &'a mut [u8]) -> Option<Self> {
+        (buf.len() >= MAX_HEADER_LEN).then_some(Self {
+            buf,
+            pos: MAX_HEADER_LEN,
+        })
+    }
+
+    fn remaining(&mut self) -> &mut [u8] {
+        &mut self.buf[self.pos..]
+    }
+
+    fn advance(&mut self, len: usize) -> Option<()> {
+        self.pos = self
+            .pos
+            .checked_add(len)
+            .filter(|pos| *pos <= self.buf.len())?;
+        Some(())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.remaining()
+            .get_mut(..bytes.len())?
+            .copy_from_slice(bytes);
+        self.advance(bytes.len())
+    }
+
+    fn u8(&mut self, value: u8) -> Option<()> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn str(&mut self, value: &str) -> Option<()> {
+        self.u16(u16::try_from(value.len()).ok()?)?;
+        self.bytes(value.as_bytes())
+    }
+
+    /// Prepends the fixed header and moves the packet to the front of the
+    /// buffer, returning its total length.
+    fn finish(self, first_byte: u8) -> Option<usize> {
+        let body_len = self.pos - MAX_HEADER_LEN;
+        let mut header = [0u8; MAX_HEADER_LEN];
+        header[0] = first_byte;
+        let header_len = 1 + encode_remaining_length(body_len, &mut header[1..])?;
+        self.buf.copy_within(MAX_HEADER_LEN..self.pos, header_len);
+        self.buf[..header_len].copy_from_slice(&header[..header_len]);
+        Some(header_len + body_len)
+    }
+}
+
+fn encode_remaining_length(mut len: usize, out: &mut [u8]) -> Option<usize> {
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            return Some(i + 1);
+        }
+        *byte |= 0x80;
+    }
+    None
+}
+
+/// An incoming packet the client acts on.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet<'a> {
+    ConnAck {
+        return_code: u8,
+    },
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+    },
+    SubAck,
+    PingResp,
+    /// Well-formed but not used by this client, or malformed.
+    Other(u8),
+}
+
+impl<'a> Packet<'a> {
+    fn parse(first_byte: u8, body: &'a [u8]) -> Self {
+        match first_byte & 0xF0 {
+            CONNACK if body.len() == 2 => Self::ConnAck {
+                return_code: body[1],
+            },
+            PUBLISH => Self::parse_publish(first_byte, body).unwrap_or(Self::Other(first_byte)),
+            SUBACK => Self::SubAck,
+            PINGRESP => Self::PingResp,
+            _ => Self::Other(first_byte),
+        }
+    }
+
+    fn parse_publish(first_byte: u8, body: &'a [u8]) -> Option<Self> {
+        let (len, rest) = body.split_first_chunk::<2>()?;
+        let (topic, rest) = rest.split_at_checked(usize::from(u16::from_be_bytes(*len)))?;
+        let topic = core::str::from_utf8(topic).ok()?;
+        // QoS 1 and 2 carry a packet identifier. We only subscribe at QoS 0 so
+        // the broker should never send them, but skip it rather than misparse.
+        let qos = (first_byte >> 1) & 0x03;
+        let payload = if qos == 0 { rest } else { rest.get(2..)? };
+        Some(Self::Publish { topic, payload })
+    }
+}
+
+/// Result of feeding one byte into a [`PacketBuffer`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received<'a> {
+    Packet(Packet<'a>),
+    /// The announced remaining length exceeds the buffer; the body is skipped.
+    Oversized(usize),
+}
+
+/// Reassembles MQTT packets from arbitrarily chunked socket reads.
+pub struct PacketBuffer<const N: usize> {
+    buf: [u8; N],
+    first_byte: Option<u8>,
+    /// Remaining length decoded so far, and the varint byte count.
+    remaining: usize,
+    length_bytes: u32,
+    length_done: bool,
+    len: usize,
+}
+
+impl<const N: usize> PacketBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            first_byte: None,
+            remaining: 0,
+            length_bytes: 0,
+            length_done: false,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, b: u8) -> Option<Received<'_>> {
+        let Some(first_byte) = self.first_byte else {
+            self.first_byte = Some(b);
+            return None;
+        };
+
+        if !self.length_done {
+            self.remaining += usize::from(b & 0x7F) << (7 * self.length_bytes);
+            self.length_bytes += 1;
+            if b & 0x80 != 0 && self.length_bytes < 4 {
+                return None;
+            }
+            self.length_done = true;
+            if self.remaining == 0 {
+                self.reset();
+                return Some(Received::Packet(Packet::parse(first_byte, &[])));
+            }
+            if self.remaining > N {
+                return Some(Received::Oversized(self.remaining));
+            }
+            return None;
+        }
+
+        // oversized bodies are counted but not stored
+        if self.len < N {
+            self.buf[self.len] = b;
+        }
+        self.len += 1;
+        if self.len < self.remaining {
+            return None;
+        }
+
+        let len = self.len;
+        self.reset();
+        (len <= N).then(|| Received::Packet(Packet::parse(first_byte, &self.buf[..len])))
+    }
+
+    fn reset(&mut self) {
+        self.first_byte = None;
+        self.remaining = 0;
+        self.length_bytes = 0;
+        self.length_done = false;
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for PacketBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `bytes` through a buffer, which must only have something to
+    /// report after the last one.
+    fn receive<'a, const N: usize>(
+        buf: &'a mut PacketBuffer<N>,
+        bytes: &[u8],
+    ) -> Option<Received<'a>> {
+        let (last, rest) = bytes.split_last()?;
+        for &b in rest {
+            assert_eq!(buf.push(b), None);
+        }
+        buf.push(*last)
+    }
+
+    #[test]
+    fn encodes_connect() {
+        let mut buf = [0u8; 64];
+        let connect = Connect {
+            client_id: "c",
+            keep_alive_secs: 60,
+            will: None,
+            username: None,
+            password: None,
+        };
+        let len = encode_connect(&connect, &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x10\x0d\x00\x04MQTT\x04\x02\x00\x3c\x00\x01c"
+        );
+    }
+
+    #[test]
+    fn encodes_connect_with_will_and_credentials() {
+        let mut buf = [0u8; 64];
+        let connect = Connect {
+            client_id: "c",
+            keep_alive_secs: 30,
+            will: Some(Will {
+                topic: "t",
+                payload: b"offline",
+                retain: true,
+            }),
+            username: Some("u"),
+            password: Some("p"),
+        };
+        let len = encode_connect(&connect, &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x10\x1f\x00\x04MQTT\x04\xe6\x00\x1e\x00\x01c\x00\x01t\x00\x07offline\x00\x01u\x00\x01p"
+        );
+        assert_eq!(encode_connect(&connect, &mut buf[..len - 1]), None);
+    }
+
+    #[test]
+    fn encodes_subscribe() {
+        let mut buf = [0u8; 32];
+        let len = encode_subscribe(7, &["a/b", "c"], &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x82\x0c\x00\x07\x00\x03a/b\x00\x00\x01c\x00");
+    }
+
+    #[test]
+    fn round_trips_publish() {
+        let mut buf = [0u8; 32];
+        let len = encode_publish("a/b", true, &mut buf, |dst| {
+            dst.get_mut(..2)?.copy_from_slice(b"40");
+            Some(2)
+        })
+        .unwrap();
+        assert_eq!(&buf[..len], b"\x31\x07\x00\x03a/b40");
+
+        let mut packets = PacketBuffer::<32>::new();
+        assert_eq!(
+            receive(&mut packets, &buf[..len]),
+            Some(Received::Packet(Packet::Publish {
+                topic: "a/b",
+                payload: b"40",
+            }))
+        );
+    }
+
+    #[test]
+    fn round_trips_long_publish() {
+        let payload = [b'x'; 300];
+        let mut buf = [0u8; 320];
+        let len = encode_publish("t", false, &mut buf, |dst| {
+            dst.get_mut(..payload.len())?.copy_from_slice(&payload);
+            Some(payload.len())
+        })
+        .unwrap();
+        // 303 bytes of body take two length bytes.
+        assert_eq!(&buf[..3], b"\x30\xaf\x02");
+        assert_eq!(len, 3 + 303);
+
+        let mut packets = PacketBuffer::<320>::new();
+        assert_eq!(
+            receive(&mut packets, &buf[..len]),
+            Some(Received::Packet(Packet::Publish {
+                topic: "t",
+                payload: &payload,
+            }))
+        );
+
+        // Too long for the buffer: announced once the length is known, then
+        // skipped so the next packet still parses.
+        let mut small = PacketBuffer::<64>::new();
+        assert_eq!(
+            receive(&mut small, &buf[..3]),
+            Some(Received::Oversized(303))
+        );
+        for &b in &buf[3..len] {
+            assert_eq!(small.push(b), None);
+        }
+        assert_eq!(
+            receive(&mut small, b"\xd0\x00"),
+            Some(Received::Packet(Packet::PingResp))
+        );
+    }
+
+    #[test]
+    fn encodes_remaining_length() {
+        let cases: [(usize, &[u8]); 8] = [
+            (0, b"\x00"),
+            (127, b"\x7f"),
+            (128, b"\x80\x01"),
+            (16_383, b"\xff\x7f"),
+            (16_384, b"\x80\x80\x01"),
+            (2_097_151, b"\xff\xff\x7f"),
+            (2_097_152, b"\x80\x80\x80\x01"),
+            (268_435_455, b"\xff\xff\xff\x7f"),
+        ];
+        for (len, expected) in cases {
+            let mut out = [0u8; 4];
+            let n = encode_remaining_length(len, &mut out).unwrap();
+            assert_eq!(&out[..n], expected, "{}", len);
+        }
+        assert_eq!(encode_remaining_length(268_435_456, &mut [0u8; 4]), None);
+        assert_eq!(encode_remaining_length(128, &mut [0u8; 1]), None);
+    }
+
+    #[test]
+    fn parses_connack_and_suback() {
+        let mut packets = PacketBuffer::<8>::new();
+        assert_eq!(
+            receive(&mut packets, b"\x20\x02\x00\x05"),
+            Some(Received::Packet(Packet::ConnAck { return_code: 5 }))
+        );
+        assert_eq!(
+            receive(&mut packets, b"\x90\x03\x00\x01\x00"),
+            Some(Received::Packet(Packet::SubAck))
+        );
+    }
+}
@@ -1,277 +1,612 @@
-extern crate alloc;
-
-use embassy_time::{Duration, Timer};
-use embedded_io_async::Write;
-use log::{debug, error, info, trace};
-use serde::Deserialize;
-
-use crate::{CLIENT_UUID, RECONNECT_DELAY_MS, lineat_motor::LinearMotorController};
-
-#[derive(Deserialize)]
-struct IncomingCommand<'a> {
-    #[serde(rename = "type")]
-    cmd_type: &'a str,
-    #[serde(default)]
-    id: Option<u32>,
-    #[serde(default)]
-    value: Option<u32>,
+use core::cell::{Cell, RefCell};
+
+use curtain_protocol::auth::{AuthError, Message, Verifier};
+#[cfg(feature = "binary-protocol")]
+use curtain_protocol::binary::{self, Frame, FrameBuffer};
+use curtain_protocol::{
+    Command, CommandError, IncomingCommand, Reply, encode_reply, encode_sequenced_reply,
+    line_buffer::{Line, LineBuffer},
+    parse_command,
+};
+use embassy_futures::select::select;
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::{State, TcpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_io_async::{Read, Write};
+use esp_hal::rng::Rng;
+use heapless::Deque;
+use log::{debug, error, info, trace, warn};
+
+use crate::motor_task::{self, COMMANDS, POSITION};
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::{CLIENT_UUID, RECONNECT_DELAY_MS, config, mdns};
+
+// Outgoing frames are encoded into this many bytes owned by the client, so
+// replies never touch the heap.
+#[cfg(feature = "binary-protocol")]
+const TX_FRAME_LEN: usize = binary::MAX_ENCODED_LEN;
+#[cfg(not(feature = "binary-protocol"))]
+const TX_FRAME_LEN: usize = 256;
+
+// The TLS implementation only has the client side of the handshake.
+#[cfg(feature = "tls")]
+const _: () = assert!(
+    config::LISTEN_PORT.is_none(),
+    "listening mode does not support TLS"
+);
+
+#[cfg(feature = "binary-protocol")]
+const OFFERED_FRAMING: Option<&str> = Some(binary::FRAMING);
+#[cfg(not(feature = "binary-protocol"))]
+const OFFERED_FRAMING: Option<&str> = None;
+
+/// Time the server gets after `register` to send `resume`; events wait for it.
+const RESUME_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Encoding of the frames on the current connection; always starts as JSON and
+/// is shared by the reader (which negotiates it) and the writer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Json,
+    #[cfg(feature = "binary-protocol")]
+    Postcard,
+}
+
+/// Events for the server (replies and status pushes), numbered as they happen
+/// whether or not a connection is up, so that a resumed session can replay
+/// the ones it missed.
+static EVENTS: Mutex<CriticalSectionRawMutex, RefCell<EventLog>> =
+    Mutex::new(RefCell::new(EventLog::new()));
+
+/// Signalled whenever an event is added to [`EVENTS`].
+static RECORDED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Numbers `event` and keeps it for the connection's writer, which sends it
+/// now or replays it after a reconnect.
+pub fn record_event(event: Reply<'static>) {
+    EVENTS.lock(|events| events.borrow_mut().record(event));
+    RECORDED.signal(());
+}
+
+struct EventLog {
+    /// Random per boot (set by [`TcpClient::new`]); the server echoes it to
+    /// resume.
+    session: u32,
+    next_seq: u32,
+    history: Deque<(u32, Reply<'static>), { config::REPLAY_HISTORY_LEN }>,
 }
 
-const SERVER_IP_V4: [u8; 4] = [192, 168, 178, 21]; // Raspberry Pi IP
-const SERVER_PORT: u16 = 9000; // TCP server port on the Pi
+impl EventLog {
+    const fn new() -> Self {
+        Self {
+            session: 0,
+            next_seq: 1,
+            history: Deque::new(),
+        }
+    }
 
-// Buffers must live at least as long as the TCP socket. Using 'static here is the
-// simplest way to ensure the socket does not reference stack-local data.
-static mut RX_BUFFER: [u8; 4096] = [0; 4096];
-static mut TX_BUFFER: [u8; 4096] = [0; 4096];
+    /// Numbers `event` and keeps it, dropping the oldest if full.
+    fn record(&mut self, event: Reply<'static>) {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        if self.history.is_full() {
+            self.history.pop_front();
+        }
+        let _ = self.history.push_back((seq, event));
+    }
+
+    /// Number of the latest event; 0 before the first.
+    fn last_seq(&self) -> u32 {
+        self.next_seq.wrapping_sub(1)
+    }
 
+    /// The first event numbered after `seq`. Numbers wrap, so "after" means
+    /// less than half the number space ahead.
+    fn next_after(&self, seq: u32) -> Option<(u32, Reply<'static>)> {
+        self.history
+            .iter()
+            .find(|(s, _)| is_after(*s, seq))
+            .cloned()
+    }
+
+    /// If events after `seq` were dropped, the number of the last one lost.
+    fn lost_until(&self, seq: u32) -> Option<u32> {
+        // With nothing held, nothing was dropped yet, or `seq` is from the
+        // future; either way there is nothing to replay.
+        let (oldest, _) = self.history.front()?;
+        is_after(*oldest, seq.wrapping_add(1)).then_some(oldest.wrapping_sub(1))
+    }
+}
+
+fn is_after(seq: u32, other: u32) -> bool {
+    (1..=i32::MAX as u32).contains(&seq.wrapping_sub(other))
+}
+
+/// How the writer starts a connection, as decided by the server's first frame.
+#[derive(Clone, Copy)]
+enum Start {
+    /// Replay the events after this sequence number.
+    Resume(u32),
+    /// The server did not ask to resume.
+    Afresh,
+    /// A resume of an unknown session.
+    UnknownSession,
+}
+
+/// Endpoint of the current connection's server (or controller, in listening
+/// mode), for diagnostics; `None` while disconnected.
+pub static ACTIVE_SERVER: Mutex<CriticalSectionRawMutex, Cell<Option<IpEndpoint>>> =
+    Mutex::new(Cell::new(None));
+
+/// Connection to the server; commands are forwarded to the motor task through
+/// [`COMMANDS`] and every event recorded with [`record_event`] is written
+/// back.
+///
+/// In listening mode ([`config::LISTEN_PORT`]) the "server" is a controller
+/// that connects to the device instead; the protocol is the same, starting
+/// with the device's `register` frame.
+///
+/// The socket is created once over caller-provided buffers and reused for
+/// every reconnect.
 pub struct TcpClient<'a> {
-    socket: Option<embassy_net::tcp::TcpSocket<'a>>,
-    motor_controller: LinearMotorController<'a>,
-    cached_value: u8,
+    stack: Stack<'a>,
+    socket: TcpSocket<'a>,
+    tx_frame: [u8; TX_FRAME_LEN],
+    /// Candidate dialled next: an index into [`config::SERVERS`], or their
+    /// length for mDNS discovery.
+    server: usize,
+    #[cfg(feature = "tls")]
+    tls_buffers: &'static mut tls::RecordBuffers,
 }
 
 impl<'a> TcpClient<'a> {
-    pub async fn new(stepper_controller: LinearMotorController<'a>) -> Self {
+    pub async fn new(stack: Stack<'a>, rx_buffer: &'a mut [u8], tx_buffer: &'a mut [u8]) -> Self {
+        let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+        socket.set_timeout(None);
+        EVENTS.lock(|events| events.borrow_mut().session = Rng::new().random());
         Self {
-            socket: None,
-            motor_controller: stepper_controller,
-            cached_value: 0,
+            stack,
+            socket,
+            tx_frame: [0; TX_FRAME_LEN],
+            server: 0,
+            #[cfg(feature = "tls")]
+            tls_buffers: tls::record_buffers(),
         }
     }
 
-    pub async fn connect(&mut self, stack: &'a embassy_net::Stack<'a>) {
-        #[allow(static_mut_refs)]
-        let socket =
-            unsafe { embassy_net::tcp::TcpSocket::new(*stack, &mut RX_BUFFER, &mut TX_BUFFER) };
-
-        self.socket = Some(socket);
-
-        if let Some(socket) = self.socket.as_mut() {
-            socket.set_timeout(None);
-            let address = embassy_net::IpAddress::Ipv4(SERVER_IP_V4.into());
-            info!(
-                "Connecting to {}.{}.{}.{}:{} ...",
-                SERVER_IP_V4[0], SERVER_IP_V4[1], SERVER_IP_V4[2], SERVER_IP_V4[3], SERVER_PORT
-            );
-            match socket.connect((address, SERVER_PORT)).await {
-                Ok(()) => {
-                    info!("TCP connected");
-                }
-                Err(e) => {
-                    error!("Connect error: {:?}", e);
-                    Timer::after(Duration::from_millis(RECONNECT_DELAY_MS)).await;
-                }
+    /// Returns whether the connection was established: dialled out to the
+    /// server, or accepted from a controller if [`config::LISTEN_PORT`] is set.
+    pub async fn connect(&mut self) -> bool {
+        // A socket can only connect or listen from the closed state; drop
+        // whatever is left of the previous connection first.
+        if self.socket.state() != State::Closed {
+            self.socket.abort();
+            let _ = self.socket.flush().await;
+        }
+
+        match config::LISTEN_PORT {
+            Some(port) => self.accept(port).await,
+            None => self.dial().await,
+        }
+    }
+
+    /// Tries the current candidate of [`config::SERVERS`] (or the mDNS
+    /// fallback after them); on failure the next attempt moves on to the next
+    /// one, while a server that worked is tried first again next time.
+    async fn dial(&mut self) -> bool {
+        let Some(endpoint) = server_endpoint(self.stack, self.server).await else {
+            error!("Server not found");
+            self.next_server();
+            Timer::after(Duration::from_millis(RECONNECT_DELAY_MS)).await;
+            return false;
+        };
+        info!("Connecting to {} ...", endpoint);
+        match self.socket.connect(endpoint).await {
+            Ok(()) => {
+                info!("TCP connected");
+                ACTIVE_SERVER.lock(|active| active.set(Some(endpoint)));
+                true
+            }
+            Err(e) => {
+                error!("Connect error: {:?}", e);
+                self.next_server();
+                Timer::after(Duration::from_millis(RECONNECT_DELAY_MS)).await;
+                false
             }
         }
     }
 
-    pub async fn serve(&mut self) {
-        // Read newline-delimited messages and log them for now.
-        let mut line_buf = [0u8; 512];
-        let mut line_len: usize = 0;
-        let mut chunk = [0u8; 128];
-
-        self.register().await;
-
-        'read_loop: loop {
-            match self.socket.as_mut().unwrap().read(&mut chunk).await {
-                Ok(0) => {
-                    info!("Server closed connection");
-                    break 'read_loop;
-                }
-                Ok(n) => {
-                    trace!("RX chunk ({} bytes): {:02X?}", n, &chunk[..n]);
-                    for &b in &chunk[..n] {
-                        if b == b'\n' {
-                            // process the completed line
-                            let line = &line_buf[..line_len];
-                            if let Ok(mut s) = core::str::from_utf8(line) {
-                                // Trim CR if present
-                                s = s.trim_end_matches('\r');
-                                if !s.is_empty() {
-                                    debug!("RX line: {}", s);
-                                    self.handle_line(s).await;
-                                }
-                            } else {
-                                error!("Received non-UTF8 line ({} bytes), ignoring", line_len);
-                            }
-                            line_len = 0;
-                        } else if line_len < line_buf.len() {
-                            line_buf[line_len] = b;
-                            line_len += 1;
-                        } else {
-                            // overflow; drop the line
-                            error!("Line too long; dropping");
-                            line_len = 0;
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Read error: {:?}", e);
-                    break 'read_loop;
-                }
+    fn next_server(&mut self) {
+        // One candidate per configured server, plus mDNS discovery.
+        self.server = (self.server + 1) % (config::SERVERS.len() + 1);
+    }
+
+    async fn accept(&mut self, port: u16) -> bool {
+        info!("Waiting for a controller on port {} ...", port);
+        match self.socket.accept(port).await {
+            Ok(()) => {
+                let endpoint = self.socket.remote_endpoint();
+                info!("Controller connected from {:?}", endpoint);
+                ACTIVE_SERVER.lock(|active| active.set(endpoint));
+                true
+            }
+            Err(e) => {
+                error!("Accept error: {:?}", e);
+                Timer::after(Duration::from_millis(RECONNECT_DELAY_MS)).await;
+                false
             }
         }
     }
 
-    pub async fn register(&mut self) {
-        let reg = alloc::format!(r#"{{"type":"register","uuid":"{}"}}"#, CLIENT_UUID);
-        debug!("TX: {}", reg);
-        if let Err(e) = self
-            .socket
-            .as_mut()
-            .unwrap()
-            .write_all(reg.as_bytes())
-            .await
+    /// Runs the connection until the server closes it or an I/O error occurs.
+    ///
+    /// The socket halves borrow the socket, so rather than being spawned they run
+    /// as two concurrent futures; either one finishing ends the connection.
+    pub async fn serve(&mut self) {
+        #[cfg(not(feature = "tls"))]
         {
-            error!("Register write error: {:?}", e);
-            // reconnect
+            let (mut reader, mut writer) = self.socket.split();
+            session(&mut reader, &mut writer, &mut self.tx_frame).await;
         }
-        if let Err(e) = self.socket.as_mut().unwrap().write_all(b"\n").await {
-            error!("Register newline write error: {:?}", e);
+
+        #[cfg(feature = "tls")]
+        {
+            let (reader, writer) = self.socket.split();
+            let halves = tls::Halves::new(reader, writer);
+            if let Some(mut connection) = tls::open(halves.socket(), self.tls_buffers).await {
+                let (mut reader, mut writer) = connection.split();
+                session(&mut reader, &mut writer, &mut self.tx_frame).await;
+            }
         }
-        info!("Sent register");
+
+        ACTIVE_SERVER.lock(|active| active.set(None));
     }
+}
 
-    async fn handle_line(&mut self, s: &str) {
-        // Parse with serde-json-core; ignore on failure
-        match serde_json_core::de::from_str::<IncomingCommand>(s) {
-            Ok((cmd, _rest)) => {
-                match cmd.cmd_type {
-                    "set_value" => {
-                        if let (Some(id), Some(v)) = (cmd.id, cmd.value) {
-                            if v <= 100 {
-                                self.set_value(id, v).await;
-                                return;
-                            }
+/// Registers with the server, then relays commands and replies until either
+/// direction fails.
+async fn session(reader: &mut impl Read, writer: &mut impl Write, tx_frame: &mut [u8]) {
+    // Events from the handshake on are this connection's to send, whether or
+    // not the server resumes.
+    let (session, handshake) = EVENTS.lock(|events| {
+        let events = events.borrow();
+        (events.session, events.last_seq())
+    });
+    let framing = Cell::new(Framing::Json);
+    // Whether the server resumes, once its first frame says so.
+    let start = Signal::<NoopRawMutex, Start>::new();
+    let verifier = config::COMMAND_SECRET.map(|secret| Verifier::new(secret, Rng::new().random()));
 
-                            // Invalid range
-                            let msg = alloc::format!(
-                                r#"{{"type":"error","id":{},"message":"value out of range 0..100"}}"#,
-                                id
-                            );
-                            debug!("TX: {}", msg);
-                            if let Err(e) = self
-                                .socket
-                                .as_mut()
-                                .unwrap()
-                                .write_all(msg.as_bytes())
-                                .await
-                            {
-                                error!("Write error (error set_value id={}): {:?}", id, e);
+    let register = Reply::Register {
+        uuid: CLIENT_UUID,
+        framing: OFFERED_FRAMING,
+        challenge: verifier.as_ref().map(Verifier::challenge),
+        session: Some(session),
+    };
+    if send(writer, tx_frame, Framing::Json, None, &register)
+        .await
+        .is_err()
+    {
+        return;
+    }
+    info!("Sent register");
+
+    select(
+        read_loop(reader, &framing, verifier, session, &start),
+        write_loop(writer, tx_frame, &framing, handshake, &start),
+    )
+    .await;
+}
+
+/// Resolves candidate `index`: an entry of [`config::SERVERS`] through DNS,
+/// or past their end, mDNS discovery of [`config::SERVER_MDNS_SERVICE`].
+async fn server_endpoint(stack: Stack<'_>, index: usize) -> Option<IpEndpoint> {
+    let Some(&(hostname, port)) = config::SERVERS.get(index) else {
+        return mdns::discover(stack, config::SERVER_MDNS_SERVICE).await;
+    };
+    match stack.dns_query(hostname, DnsQueryType::A).await {
+        Ok(addresses) => addresses
+            .first()
+            .map(|&address| IpEndpoint::new(address, port)),
+        Err(e) => {
+            warn!("DNS lookup of {} failed: {:?}", hostname, e);
+            None
+        }
+    }
+}
+
+async fn read_loop(
+    reader: &mut impl Read,
+    framing: &Cell<Framing>,
+    mut verifier: Option<Verifier<'_>>,
+    session: u32,
+    start: &Signal<NoopRawMutex, Start>,
+) {
+    // Read newline-delimited messages (or binary frames once negotiated).
+    let mut line_buf = LineBuffer::<512>::new();
+    #[cfg(feature = "binary-protocol")]
+    let mut frame_buf = FrameBuffer::<{ binary::MAX_FRAME_LEN }>::new();
+    let mut chunk = [0u8; 128];
+
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) => {
+                info!("Server closed connection");
+                return;
+            }
+            Ok(n) => {
+                trace!("RX chunk ({} bytes): {:02X?}", n, &chunk[..n]);
+                // The framing may change mid-chunk, so it is checked per byte.
+                for &b in &chunk[..n] {
+                    match framing.get() {
+                        Framing::Json => match line_buf.push(b) {
+                            Some(Line::Complete(s)) => {
+                                debug!("RX line: {}", s);
+                                if let Some(next) =
+                                    handle_line(s, verifier.as_mut(), session, start).await
+                                {
+                                    framing.set(next);
+                                }
                             }
-                            if let Err(e) = self.socket.as_mut().unwrap().write_all(b"\n").await {
-                                error!("Write error (newline error set_value id={}): {:?}", id, e);
+                            Some(Line::NonUtf8(len)) => {
+                                error!("Received non-UTF8 line ({} bytes), ignoring", len);
                             }
-                        } else if let Some(id) = cmd.id {
-                            let msg = alloc::format!(
-                                r#"{{"type":"error","id":{},"message":"missing value"}}"#,
-                                id
-                            );
-                            debug!("TX: {}", msg);
-                            if let Err(e) = self
-                                .socket
-                                .as_mut()
-                                .unwrap()
-                                .write_all(msg.as_bytes())
-                                .await
-                            {
-                                error!("Write error (error missing value id={}): {:?}", id, e);
+                            Some(Line::Overflow) => {
+                                error!("Line too long; dropping");
                             }
-                            if let Err(e) = self.socket.as_mut().unwrap().write_all(b"\n").await {
-                                error!(
-                                    "Write error (newline error missing value id={}): {:?}",
-                                    id, e
-                                );
+                            None => {}
+                        },
+                        #[cfg(feature = "binary-protocol")]
+                        Framing::Postcard => match frame_buf.push(b) {
+                            Some(Frame::Complete(payload)) => {
+                                trace!("RX frame ({} bytes)", payload.len());
+                                handle_frame(payload, verifier.as_mut()).await;
                             }
-                        }
-                    }
-                    "get_value" => {
-                        if let Some(id) = cmd.id {
-                            self.get_value(id).await;
-                        }
-                    }
-                    "calibrate" => {
-                        if let Some(id) = cmd.id {
-                            self.calibrate(id).await;
-                        }
-                    }
-                    _ => {
-                        // Ignore unknown types
+                            Some(Frame::Oversized(len)) => {
+                                error!("Frame too long ({} bytes); dropping", len);
+                            }
+                            None => {}
+                        },
                     }
                 }
             }
             Err(e) => {
-                let _ = e; // ignore parse errors; robustness over strictness
+                error!("Read error: {:?}", e);
+                return;
             }
         }
     }
+}
 
-    async fn set_value(&mut self, id: u32, v: u32) {
-        info!("set_value id={} value={}", id, v);
-        self.cached_value = v as u8;
-        // Acknowledge success
-        let msg = alloc::format!(r#"{{"type":"ack","id":{},"ok":true}}"#, id);
-        debug!("TX: {}", msg);
-        if let Err(e) = self
-            .socket
-            .as_mut()
-            .unwrap()
-            .write_all(msg.as_bytes())
-            .await
-        {
-            error!("Write error (ack set_value id={}): {:?}", id, e);
+/// Forwards the command on `s` to the motor task; returns the framing to switch
+/// to if the line negotiated one.
+async fn handle_line(
+    s: &str,
+    verifier: Option<&mut Verifier<'_>>,
+    session: u32,
+    start: &Signal<NoopRawMutex, Start>,
+) -> Option<Framing> {
+    // Parse with serde-json-core; ignore on failure
+    let Some(cmd) = parse_command(s) else {
+        // ignore parse errors; robustness over strictness
+        return None;
+    };
+    if cmd.cmd_type == "resume" {
+        let (Some(token), Some(seq)) = (cmd.session, cmd.seq) else {
+            warn!("Cannot resume without a session and seq; starting afresh");
+            start.signal(Start::UnknownSession);
+            return None;
+        };
+        if !authentic(
+            verifier,
+            Message::Resume {
+                session: token,
+                seq,
+            },
+            &cmd,
+        ) {
+            return None;
         }
-        if let Err(e) = self.socket.as_mut().unwrap().write_all(b"\n").await {
-            error!("Write error (newline ack set_value id={}): {:?}", id, e);
+        if token == session {
+            info!("Resuming session after event {}", seq);
+            start.signal(Start::Resume(seq));
+        } else {
+            warn!("Cannot resume session {}; starting afresh", token);
+            start.signal(Start::UnknownSession);
         }
+        return None;
     }
-
-    async fn get_value(&mut self, id: u32) {
-        info!("get_value id={} -> {}", id, self.cached_value);
-        let msg = alloc::format!(
-            r#"{{"type":"value","id":{},"value":{}}}"#,
-            id,
-            self.cached_value
-        );
-        debug!("TX: {}", msg);
-        if let Err(e) = self
-            .socket
-            .as_mut()
-            .unwrap()
-            .write_all(msg.as_bytes())
-            .await
-        {
-            error!("Write error (value id={}): {:?}", id, e);
+    if cmd.cmd_type == "set_framing" {
+        let framing = cmd.framing.unwrap_or_default();
+        if !authentic(verifier, Message::SetFraming(framing), &cmd) {
+            return None;
         }
-        if let Err(e) = self.socket.as_mut().unwrap().write_all(b"\n").await {
-            error!("Write error (newline value id={}): {:?}", id, e);
+        return match cmd.framing {
+            #[cfg(feature = "binary-protocol")]
+            Some(binary::FRAMING) => {
+                info!("Switching to {} framing", binary::FRAMING);
+                Some(Framing::Postcard)
+            }
+            other => {
+                error!("Unsupported framing {:?}; staying on JSON", other);
+                None
+            }
+        };
+    }
+    // A command first means there is nothing to resume; one after a resume
+    // must not undo it before the writer saw it.
+    if !start.signaled() {
+        start.signal(Start::Afresh);
+    }
+    match Command::try_from(&cmd) {
+        Ok(command) => {
+            let verified = verifier.map(|v| v.verify_hex(&command, cmd.counter, cmd.mac));
+            forward(command, verified).await;
         }
+        Err(CommandError::MissingValue { id }) => {
+            record_event(Reply::Error {
+                id,
+                message: "missing value",
+            });
+        }
+        // Ignore unknown types and commands without an id
+        Err(CommandError::Unsupported) => {}
     }
+    None
+}
 
-    async fn calibrate(&mut self, id: u32) {
-        info!("calibrate start (id={})", id);
-        self.calibrate_routine().await;
-        info!("calibrate done (id={})", id);
-        let msg = alloc::format!(r#"{{"type":"ack","id":{},"ok":true}}"#, id);
-        debug!("TX: {}", msg);
-        if let Err(e) = self
-            .socket
-            .as_mut()
-            .unwrap()
-            .write_all(msg.as_bytes())
-            .await
-        {
-            error!("Write error (ack calibrate id={}): {:?}", id, e);
+/// Checks the MAC of a frame that steers the connection rather than the motor;
+/// without a secret every frame passes.
+fn authentic(
+    verifier: Option<&mut Verifier<'_>>,
+    message: Message<'_>,
+    cmd: &IncomingCommand<'_>,
+) -> bool {
+    match verifier.map(|v| v.verify_hex(message, cmd.counter, cmd.mac)) {
+        Some(Err(e)) => {
+            warn!("Rejected {}: {:?}", cmd.cmd_type, e);
+            false
         }
-        if let Err(e) = self.socket.as_mut().unwrap().write_all(b"\n").await {
-            error!("Write error (newline ack calibrate id={}): {:?}", id, e);
+        _ => true,
+    }
+}
+
+#[cfg(feature = "binary-protocol")]
+async fn handle_frame(payload: &[u8], verifier: Option<&mut Verifier<'_>>) {
+    let decoded = match verifier {
+        None => binary::decode_command(payload).map(|cmd| (cmd, None)),
+        Some(verifier) => {
+            binary::decode_authenticated_command(payload).map(|(cmd, counter, mac)| {
+                let verified = verifier.verify(&cmd, counter, mac);
+                (cmd, Some(verified))
+            })
+        }
+    };
+    let Some((cmd, verified)) = decoded else {
+        error!("Undecodable frame ({} bytes), ignoring", payload.len());
+        return;
+    };
+    debug!("RX frame: {:?}", cmd);
+    forward(cmd, verified).await;
+}
+
+/// Sends `command` to the motor task unless its authentication (if required)
+/// failed, in which case the server gets an `auth_failed` reply instead.
+async fn forward(command: Command, verified: Option<Result<(), AuthError>>) {
+    match verified {
+        Some(Err(e)) => {
+            let id = command.id();
+            warn!("Rejected command {}: {:?}", id, e);
+            record_event(Reply::AuthFailed { id });
         }
+        _ => motor_task::submit(COMMANDS.dyn_sender(), command).await,
     }
+}
+
+async fn write_loop(
+    writer: &mut impl Write,
+    tx_frame: &mut [u8],
+    framing: &Cell<Framing>,
+    handshake: u32,
+    start: &Signal<NoopRawMutex, Start>,
+) {
+    // Events wait for the server's decision, so that they go out after the
+    // ones replayed. Later resumes are ignored for the same reason.
+    let start = with_timeout(RESUME_TIMEOUT, start.wait())
+        .await
+        .unwrap_or(Start::Afresh);
+    let mut sent = match start {
+        Start::Resume(last_seen) => last_seen,
+        Start::Afresh => handshake,
+        Start::UnknownSession => {
+            warn!("Events before the connection are lost");
+            if send(writer, tx_frame, framing.get(), None, &Reply::ResumeFailed)
+                .await
+                .is_err()
+            {
+                return;
+            }
+            handshake
+        }
+    };
+    // However much is replayed, the server ends up with the position as it
+    // is now.
+    record_event(Reply::Status {
+        value: POSITION.lock(Cell::get),
+    });
 
-    async fn calibrate_routine(&mut self) {
-        // Placeholder: simulate calibration delay
-        Timer::after(Duration::from_millis(200)).await;
+    loop {
+        let next = EVENTS.lock(|events| {
+            let events = events.borrow();
+            match events.lost_until(sent) {
+                Some(lost) => Err(lost),
+                None => Ok(events.next_after(sent)),
+            }
+        });
+        match next {
+            Ok(Some((seq, event))) => {
+                if send(writer, tx_frame, framing.get(), Some(seq), &event)
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                sent = seq;
+            }
+            Ok(None) => RECORDED.wait().await,
+            Err(lost) => {
+                // Dropped from the history before they were sent; the server
+                // gets what is still held, then the current position.
+                warn!("Events {}..={} are lost", sent.wrapping_add(1), lost);
+                if send(writer, tx_frame, framing.get(), None, &Reply::ResumeFailed)
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                sent = lost;
+                record_event(Reply::Status {
+                    value: POSITION.lock(Cell::get),
+                });
+            }
+        }
     }
 }
+
+/// Writes one reply in the given framing, numbered with `seq` if it is an
+/// event.
+async fn send<W: Write>(
+    writer: &mut W,
+    tx_frame: &mut [u8],
+    framing: Framing,
+    seq: Option<u32>,
+    reply: &Reply<'_>,
+) -> Result<(), W::Error> {
+    debug!("TX {:?}: {:?}", seq, reply);
+    let encoded = match (framing, seq) {
+        (Framing::Json, None) => encode_reply(reply, tx_frame),
+        (Framing::Json, Some(seq)) => encode_sequenced_reply(seq, reply, tx_frame),
+        #[cfg(feature = "binary-protocol")]
+        (Framing::Postcard, None) => binary::encode_reply(reply, tx_frame),
+        #[cfg(feature = "binary-protocol")]
+        (Framing::Postcard, Some(seq)) => binary::encode_sequenced_reply(seq, reply, tx_frame),
+    };
+    let Some(len) = encoded else {
+        // Not a connection problem; drop the frame and keep going.
+        error!("Encode error ({:?})", reply);
+        return Ok(());
+    };
+    // TLS only emits a record on flush.
+    let result = match writer.write_all(&tx_frame[..len]).await {
+        Ok(()) => writer.flush().await,
+        Err(e) => Err(e),
+    };
+    result.inspect_err(|e| {
+        error!("Write error ({:?}): {:?}", reply, e);
+    })
+}
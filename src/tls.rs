@@ -0,0 +1,134 @@
+//! TLS 1.3 on the server connection, authenticated with a pre-shared key
+//! ([`config::TLS_PSK`]): only a server holding the key can complete the
+//! handshake, so no certificate is needed on either side.
+//!
+//! Try it against a local server with
+//! `openssl s_server -accept 9000 -tls1_3 -nocert -ciphersuites TLS_AES_128_GCM_SHA256 -psk_identity curtain -psk <key as hex>`.
+
+use embassy_net::tcp::{self, TcpReader, TcpWriter};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_io_async::{ErrorType, Read, Write};
+use embedded_tls::{Aes128GcmSha256, TlsConfig, TlsConnection, TlsContext, UnsecureProvider};
+use esp_hal::rng::Rng;
+use log::{error, info};
+use rand_core::{CryptoRng, RngCore};
+use static_cell::ConstStaticCell;
+
+use crate::config;
+
+/// Record buffers of the connection; too large for the stack.
+pub struct RecordBuffers {
+    read: [u8; config::TLS_READ_RECORD_LEN],
+    write: [u8; config::TLS_WRITE_RECORD_LEN],
+}
+
+static RECORD_BUFFERS: ConstStaticCell<RecordBuffers> = ConstStaticCell::new(RecordBuffers {
+    read: [0; config::TLS_READ_RECORD_LEN],
+    write: [0; config::TLS_WRITE_RECORD_LEN],
+});
+
+/// Takes the record buffers; panics if called twice.
+pub fn record_buffers() -> &'static mut RecordBuffers {
+    RECORD_BUFFERS.take()
+}
+
+/// The halves of a split TCP socket, shared by the TLS reader and writer.
+pub struct Halves<'s> {
+    reader: Mutex<NoopRawMutex, TcpReader<'s>>,
+    writer: Mutex<NoopRawMutex, TcpWriter<'s>>,
+}
+
+impl<'s> Halves<'s> {
+    pub fn new(reader: TcpReader<'s>, writer: TcpWriter<'s>) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn socket(&self) -> SharedSocket<'_, 's> {
+        SharedSocket { halves: self }
+    }
+}
+
+/// A copyable handle to [`Halves`], as splitting a [`TlsConnection`] needs.
+/// Reads and writes lock different halves, so they never wait for each other.
+#[derive(Clone, Copy)]
+pub struct SharedSocket<'h, 's> {
+    halves: &'h Halves<'s>,
+}
+
+impl ErrorType for SharedSocket<'_, '_> {
+    type Error = tcp::Error;
+}
+
+impl Read for SharedSocket<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.halves.reader.lock().await.read(buf).await
+    }
+}
+
+impl Write for SharedSocket<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.halves.writer.lock().await.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.halves.writer.lock().await.flush().await
+    }
+}
+
+/// The hardware RNG; a true random source while the radio is running.
+struct HwRng(Rng);
+
+impl RngCore for HwRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.random()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (u64::from(self.0.random()) << 32) | u64::from(self.0.random())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            chunk.copy_from_slice(&self.0.random().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for HwRng {}
+
+pub type Connection<'a, 'h, 's> = TlsConnection<'a, SharedSocket<'h, 's>, Aes128GcmSha256>;
+
+/// Runs the handshake over an already connected socket.
+pub async fn open<'a, 'h, 's>(
+    socket: SharedSocket<'h, 's>,
+    buffers: &'a mut RecordBuffers,
+) -> Option<Connection<'a, 'h, 's>> {
+    if config::TLS_PSK == config::TLS_PSK_PLACEHOLDER {
+        error!("TLS_PSK is the placeholder; build with CURTAIN_TLS_PSK set");
+        return None;
+    }
+    let mut connection = TlsConnection::new(socket, &mut buffers.read, &mut buffers.write);
+    let config = TlsConfig::new().with_psk(config::TLS_PSK, &[config::TLS_PSK_IDENTITY]);
+    // The server proves itself by knowing the key; there is no certificate
+    // for a provider to verify.
+    let provider = UnsecureProvider::new::<Aes128GcmSha256>(HwRng(Rng::new()));
+    match connection.open(TlsContext::new(&config, provider)).await {
+        Ok(()) => {
+            info!("TLS session established");
+            Some(connection)
+        }
+        Err(e) => {
+            error!("TLS handshake error: {:?}", e);
+            None
+        }
+    }
+}
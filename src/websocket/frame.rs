@@ -0,0 +1,335 @@
+//! WebSocket (RFC 6455) framing for a server: decodes masked client frames,
+//! rejecting unmasked ones, and encodes unmasked, unfragmented ones, plus the
+//! opening handshake's accept key. No hardware dependencies.
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use sha1::{Digest, Sha1};
+
+pub const OPCODE_CONTINUATION: u8 = 0x0;
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xA;
+
+const FIN: u8 = 0x80;
+const MASKED: u8 = 0x80;
+
+/// Largest header this module writes: server frames are at most 64 KiB.
+const MAX_HEADER_LEN: usize = 4;
+
+/// Largest client header: 8 byte length and mask key.
+const MAX_CLIENT_HEADER_LEN: usize = 14;
+
+const HANDSHAKE_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// `Sec-WebSocket-Accept` for a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> [u8; 28] {
+    let digest = Sha1::new()
+        .chain_update(key.as_bytes())
+        .chain_update(HANDSHAKE_GUID)
+        .finalize();
+    let mut accept = [0u8; 28];
+    // A 20 byte digest is always 28 base64 characters.
+    let _ = STANDARD.encode_slice(digest, &mut accept);
+    accept
+}
+
+/// Encodes one final frame whose payload is written in place by `payload`,
+/// which returns the payload length.
+pub fn encode_frame(
+    opcode: u8,
+    buf: &mut [u8],
+    payload: impl FnOnce(&mut [u8]) -> Option<usize>,
+) -> Option<usize> {
+    let len = payload(buf.get_mut(MAX_HEADER_LEN..)?)?;
+    let len16 = u16::try_from(len).ok()?;
+
+    let mut header = [FIN | opcode, 0, 0, 0];
+    let header_len = if len < 126 {
+        header[1] = len as u8;
+        2
+    } else {
+        header[1] = 126;
+        header[2..4].copy_from_slice(&len16.to_be_bytes());
+        4
+    };
+    buf.copy_within(MAX_HEADER_LEN..MAX_HEADER_LEN + len, header_len);
+    buf[..header_len].copy_from_slice(&header[..header_len]);
+    Some(header_len + len)
+}
+
+/// Result of feeding one byte into a [`FrameBuffer`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received<'a> {
+    /// A complete frame with its payload unmasked.
+    Frame {
+        fin: bool,
+        opcode: u8,
+        payload: &'a [u8],
+    },
+    /// The announced payload length exceeds the buffer; the payload is skipped.
+    Oversized(u64),
+    /// A frame without a mask; the server must close the connection.
+    Unmasked,
+}
+
+/// Reassembles client frames from arbitrarily chunked socket reads.
+pub struct FrameBuffer<const N: usize> {
+    buf: [u8; N],
+    header: [u8; MAX_CLIENT_HEADER_LEN],
+    header_len: usize,
+    mask: [u8; 4],
+    expected: u64,
+    len: u64,
+}
+
+impl<const N: usize> FrameBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            header: [0; MAX_CLIENT_HEADER_LEN],
+            header_len: 0,
+            mask: [0; 4],
+            expected: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, b: u8) -> Option<Received<'_>> {
+        if self.header_len < 2 || self.header_len < self.full_header_len() {
+            self.header[self.header_len] = b;
+            self.header_len += 1;
+            if self.header_len == 2 && self.header[1] & MASKED == 0 {
+                self.header_len = 0;
+                return Some(Received::Unmasked);
+            }
+            if self.header_len < 2 || self.header_len < self.full_header_len() {
+                return None;
+            }
+            return self.start_payload();
+        }
+
+        // oversized payloads are counted but not stored
+        if let Some(slot) = usize::try_from(self.len)
+            .ok()
+            .and_then(|i| self.buf.get_mut(i))
+        {
+            *slot = b ^ self.mask[(self.len % 4) as usize];
+        }
+        self.len += 1;
+        if self.len < self.expected {
+            return None;
+        }
+        self.finish()
+    }
+
+    /// Bytes of extended payload length announced by the second header byte.
+    fn length_len(&self) -> usize {
+        match self.header[1] & 0x7F {
+            126 => 2,
+            127 => 8,
+            _ => 0,
+        }
+    }
+
+    /// Header length announced by the first two header bytes, mask included.
+    fn full_header_len(&self) -> usize {
+        2 + self.length_len() + 4
+    }
+
+    fn start_payload(&mut self) -> Option<Received<'_>> {
+        let (length, rest) = self.header[2..self.header_len].split_at(self.length_len());
+        self.expected = match *length {
+            [a, b] => u64::from(u16::from_be_bytes([a, b])),
+            [a, b, c, d, e, f, g, h] => u64::from_be_bytes([a, b, c, d, e, f, g, h]),
+            _ => u64::from(self.header[1] & 0x7F),
+        };
+        self.mask = rest.try_into().ok()?;
+
+        if self.expected == 0 {
+            return self.finish();
+        }
+        if self.expected > N as u64 {
+            return Some(Received::Oversized(self.expected));
+        }
+        None
+    }
+
+    fn finish(&mut self) -> Option<Received<'_>> {
+        let len = core::mem::take(&mut self.len);
+        let first = self.header[0];
+        self.header_len = 0;
+        (len <= N as u64).then(|| Received::Frame {
+            fin: first & FIN != 0,
+            opcode: first & 0x0F,
+            payload: &self.buf[..len as usize],
+        })
+    }
+}
+
+impl<const N: usize> Default for FrameBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `bytes` through a buffer, which must only have something to
+    /// report after the last one.
+    fn receive<'a, const N: usize>(
+        buf: &'a mut FrameBuffer<N>,
+        bytes: &[u8],
+    ) -> Option<Received<'a>> {
+        let (last, rest) = bytes.split_last()?;
+        for &b in rest {
+            assert_eq!(buf.push(b), None);
+        }
+        buf.push(*last)
+    }
+
+    /// Writes a masked client frame of up to 125 bytes into `buf`.
+    fn client_frame<'a>(first_byte: u8, payload: &[u8], buf: &'a mut [u8]) -> &'a [u8] {
+        const MASK: [u8; 4] = [0x37, 0xFA, 0x21, 0x3D];
+        buf[0] = first_byte;
+        buf[1] = MASKED | payload.len() as u8;
+        buf[2..6].copy_from_slice(&MASK);
+        for (i, b) in payload.iter().enumerate() {
+            buf[6 + i] = b ^ MASK[i % 4];
+        }
+        &buf[..6 + payload.len()]
+    }
+
+    #[test]
+    fn accepts_the_rfc_handshake() {
+        // RFC 6455, section 1.3.
+        assert_eq!(
+            &accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn unmasks_text() {
+        // RFC 6455, section 5.7: a single-frame masked text message.
+        let hello = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        let mut frames = FrameBuffer::<128>::new();
+        assert_eq!(
+            receive(&mut frames, hello),
+            Some(Received::Frame {
+                fin: true,
+                opcode: OPCODE_TEXT,
+                payload: b"Hello",
+            })
+        );
+        // And again, from a clean state.
+        assert_eq!(
+            receive(&mut frames, hello),
+            Some(Received::Frame {
+                fin: true,
+                opcode: OPCODE_TEXT,
+                payload: b"Hello",
+            })
+        );
+    }
+
+    #[test]
+    fn reports_fragments() {
+        let mut frames = FrameBuffer::<128>::new();
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            receive(&mut frames, client_frame(OPCODE_TEXT, b"Hel", &mut buf)),
+            Some(Received::Frame {
+                fin: false,
+                opcode: OPCODE_TEXT,
+                payload: b"Hel",
+            })
+        );
+        assert_eq!(
+            receive(
+                &mut frames,
+                client_frame(FIN | OPCODE_CONTINUATION, b"lo", &mut buf)
+            ),
+            Some(Received::Frame {
+                fin: true,
+                opcode: OPCODE_CONTINUATION,
+                payload: b"lo",
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unmasked_frames() {
+        // RFC 6455, section 5.7: the same message unmasked.
+        let mut frames = FrameBuffer::<128>::new();
+        assert_eq!(frames.push(0x81), None);
+        assert_eq!(frames.push(0x05), Some(Received::Unmasked));
+    }
+
+    #[test]
+    fn skips_oversized_payloads() {
+        let mut frames = FrameBuffer::<128>::new();
+        // 256 bytes with a 16 bit length, masked with zeroes.
+        let header = b"\x82\xfe\x01\x00\x00\x00\x00\x00";
+        assert_eq!(receive(&mut frames, header), Some(Received::Oversized(256)));
+        for _ in 0..256 {
+            assert_eq!(frames.push(0), None);
+        }
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            receive(
+                &mut frames,
+                client_frame(FIN | OPCODE_PING, b"hi", &mut buf)
+            ),
+            Some(Received::Frame {
+                fin: true,
+                opcode: OPCODE_PING,
+                payload: b"hi",
+            })
+        );
+
+        // A 64 bit length that does not even fit in memory.
+        let header = b"\x81\xff\xff\xff\xff\xff\xff\xff\xff\xff\x00\x00\x00\x00";
+        assert_eq!(
+            receive(&mut frames, header),
+            Some(Received::Oversized(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn reads_empty_close_frames() {
+        let mut frames = FrameBuffer::<128>::new();
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            receive(&mut frames, client_frame(FIN | OPCODE_CLOSE, b"", &mut buf)),
+            Some(Received::Frame {
+                fin: true,
+                opcode: OPCODE_CLOSE,
+                payload: b"",
+            })
+        );
+    }
+
+    #[test]
+    fn encodes_server_frames() {
+        let mut buf = [0u8; 256];
+        let len = encode_frame(OPCODE_TEXT, &mut buf, |dst| {
+            dst[..5].copy_from_slice(b"Hello");
+            Some(5)
+        });
+        assert_eq!(len, Some(7));
+        assert_eq!(&buf[..7], b"\x81\x05Hello");
+
+        let len = encode_frame(OPCODE_TEXT, &mut buf, |_| Some(200));
+        assert_eq!(len, Some(204));
+        assert_eq!(&buf[..4], b"\x81\x7e\x00\xc8");
+
+        let len = encode_frame(OPCODE_CLOSE, &mut buf, |_| Some(0));
+        assert_eq!(len, Some(2));
+        assert_eq!(&buf[..2], b"\x88\x00");
+    }
+}
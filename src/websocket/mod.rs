@@ -0,0 +1,276 @@
+//! WebSocket endpoint for clients that cannot open a raw TCP connection, such as
+//! browsers. Each text message carries one JSON object of the TCP protocol:
+//! commands like `{"type":"set_value","id":1,"value":40}` in, and the replies
+//! and unsolicited `status` events out.
+//!
+//! With [`config::COMMAND_SECRET`] set, the session opens with a `register`
+//! message carrying a challenge, and commands must be authenticated as on the
+//! server connection (see `curtain_protocol::auth`).
+//!
+//! Try it with `websocat ws://<device-ip>:81/`.
+
+pub mod frame;
+
+use curtain_protocol::auth::Verifier;
+use curtain_protocol::{Command, CommandError, Reply, encode_reply, parse_command};
+use embassy_futures::select::{Either, select};
+use embassy_net::Stack;
+use embassy_net::tcp::{TcpReader, TcpSocket, TcpWriter};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_io_async::Write;
+use esp_hal::rng::Rng;
+use heapless::Vec;
+use log::{debug, error, info, trace, warn};
+
+use self::frame::{
+    FrameBuffer, OPCODE_CLOSE, OPCODE_PING, OPCODE_PONG, OPCODE_TEXT, Received, encode_frame,
+};
+use crate::http::request::{self, Parsed};
+use crate::http::{read_request, shutdown};
+use crate::motor_task::{self, WS_COMMANDS, WS_REPLIES};
+use crate::{CLIENT_UUID, config};
+
+/// Time a client gets to send its opening handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest incoming message; longer ones close the connection.
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// Outgoing frames are encoded into this many bytes owned by the task.
+const TX_FRAME_LEN: usize = 256;
+
+/// Largest control frame payload allowed by RFC 6455.
+const MAX_CONTROL_LEN: usize = 125;
+
+/// Serves one client at a time on [`config::WS_PORT`]; commands go to the motor
+/// task through [`WS_COMMANDS`] and everything in [`WS_REPLIES`] is sent back.
+#[embassy_executor::task]
+pub async fn websocket_task(
+    stack: Stack<'static>,
+    rx_buffer: &'static mut [u8],
+    tx_buffer: &'static mut [u8],
+) {
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+    let mut request_buf = [0u8; config::HTTP_REQUEST_LEN];
+    let mut tx_frame = [0u8; TX_FRAME_LEN];
+
+    info!("WebSocket endpoint listening on port {}", config::WS_PORT);
+    loop {
+        if let Err(e) = socket.accept(config::WS_PORT).await {
+            error!("WebSocket accept error: {:?}", e);
+            Timer::after(Duration::from_millis(crate::RECONNECT_DELAY_MS)).await;
+            continue;
+        }
+        debug!("WebSocket connection from {:?}", socket.remote_endpoint());
+
+        if handshake(&mut socket, &mut request_buf).await {
+            info!("WebSocket client connected");
+            // Drop the status events queued while nobody was listening.
+            WS_REPLIES.clear();
+            serve(&mut socket, &mut tx_frame).await;
+        }
+        shutdown(&mut socket).await;
+    }
+}
+
+/// Answers the client's upgrade request; returns whether it was one.
+async fn handshake(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> bool {
+    let Ok(Some(len)) = with_timeout(HANDSHAKE_TIMEOUT, read_request(socket, buf)).await else {
+        return false;
+    };
+    let key = match request::parse(&buf[..len]) {
+        Parsed::Complete(request)
+            if request.method == "GET"
+                && request
+                    .header("upgrade")
+                    .is_some_and(|v| v.eq_ignore_ascii_case("websocket")) =>
+        {
+            request.header("sec-websocket-key")
+        }
+        _ => None,
+    };
+    let Some(key) = key else {
+        warn!("Not a WebSocket upgrade request");
+        let _ = socket
+            .write_all(
+                b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )
+            .await;
+        return false;
+    };
+
+    let accept = frame::accept_key(key);
+    let result = async {
+        socket
+            .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: ")
+            .await?;
+        socket.write_all(&accept).await?;
+        socket.write_all(b"\r\n\r\n").await
+    }
+    .await;
+    result
+        .inspect_err(|e| error!("WebSocket write error: {:?}", e))
+        .is_ok()
+}
+
+/// Runs the session until either side closes it, with the socket halves as two
+/// concurrent futures like [`TcpClient`](crate::tcp_client::TcpClient).
+async fn serve(socket: &mut TcpSocket<'_>, tx_frame: &mut [u8]) {
+    let (mut reader, mut writer) = socket.split();
+    let pong = Signal::<NoopRawMutex, Vec<u8, MAX_CONTROL_LEN>>::new();
+
+    let verifier = config::COMMAND_SECRET.map(|secret| Verifier::new(secret, Rng::new().random()));
+    if let Some(verifier) = &verifier {
+        let register = Reply::Register {
+            uuid: CLIENT_UUID,
+            framing: None,
+            challenge: Some(verifier.challenge()),
+            session: None,
+        };
+        let encoded = encode_frame(OPCODE_TEXT, tx_frame, |buf| {
+            encode_reply(&register, buf).map(|len| len - 1)
+        });
+        let Some(len) = encoded else {
+            error!("WebSocket encode error");
+            return;
+        };
+        if let Err(e) = writer.write_all(&tx_frame[..len]).await {
+            error!("WebSocket write error: {:?}", e);
+            return;
+        }
+    }
+
+    let close = match select(
+        read_loop(&mut reader, &pong, verifier),
+        write_loop(&mut writer, tx_frame, &pong),
+    )
+    .await
+    {
+        Either::First(close) => close,
+        Either::Second(()) => false,
+    };
+    if close && let Some(len) = encode_frame(OPCODE_CLOSE, tx_frame, |_| Some(0)) {
+        let _ = writer.write_all(&tx_frame[..len]).await;
+    }
+}
+
+/// Returns whether the connection should be closed with a close frame.
+async fn read_loop(
+    reader: &mut TcpReader<'_>,
+    pong: &Signal<NoopRawMutex, Vec<u8, MAX_CONTROL_LEN>>,
+    mut verifier: Option<Verifier<'_>>,
+) -> bool {
+    let mut frame_buf = FrameBuffer::<MAX_MESSAGE_LEN>::new();
+    let mut chunk = [0u8; 128];
+
+    loop {
+        let n = match reader.read(&mut chunk).await {
+            Ok(0) => {
+                info!("WebSocket client closed connection");
+                return false;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                error!("WebSocket read error: {:?}", e);
+                return false;
+            }
+        };
+        trace!("WS RX chunk ({} bytes): {:02X?}", n, &chunk[..n]);
+        for &b in &chunk[..n] {
+            match frame_buf.push(b) {
+                Some(Received::Frame {
+                    fin: true,
+                    opcode: OPCODE_TEXT,
+                    payload,
+                }) => handle_message(payload, verifier.as_mut()).await,
+                Some(Received::Frame {
+                    opcode: OPCODE_PING,
+                    payload,
+                    ..
+                }) => pong.signal(Vec::from_slice(payload).unwrap_or_default()),
+                Some(Received::Frame {
+                    opcode: OPCODE_CLOSE,
+                    ..
+                }) => {
+                    info!("WebSocket client closed session");
+                    return true;
+                }
+                Some(Received::Frame { fin, opcode, .. }) => {
+                    // Pongs, binary and fragmented messages.
+                    debug!("Ignoring WebSocket frame (opcode {}, fin {})", opcode, fin);
+                }
+                Some(Received::Oversized(len)) => {
+                    error!("WebSocket message too long ({} bytes); closing", len);
+                    return true;
+                }
+                Some(Received::Unmasked) => {
+                    error!("Unmasked WebSocket frame; closing");
+                    return true;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+async fn handle_message(payload: &[u8], verifier: Option<&mut Verifier<'_>>) {
+    let Ok(s) = core::str::from_utf8(payload) else {
+        error!("Received non-UTF8 text message, ignoring");
+        return;
+    };
+    debug!("WS RX: {}", s);
+    let Some(cmd) = parse_command(s) else {
+        return;
+    };
+    match Command::try_from(&cmd) {
+        Ok(command) => match verifier.map(|v| v.verify_hex(&command, cmd.counter, cmd.mac)) {
+            Some(Err(e)) => {
+                let id = command.id();
+                warn!("Rejected WebSocket command {}: {:?}", id, e);
+                WS_REPLIES.send(Reply::AuthFailed { id }).await;
+            }
+            _ => motor_task::submit(WS_COMMANDS.dyn_sender(), command).await,
+        },
+        Err(CommandError::MissingValue { id }) => {
+            WS_REPLIES
+                .send(Reply::Error {
+                    id,
+                    message: "missing value",
+                })
+                .await;
+        }
+        Err(CommandError::Unsupported) => {}
+    }
+}
+
+async fn write_loop(
+    writer: &mut TcpWriter<'_>,
+    tx_frame: &mut [u8],
+    pong: &Signal<NoopRawMutex, Vec<u8, MAX_CONTROL_LEN>>,
+) {
+    loop {
+        let encoded = match select(WS_REPLIES.receive(), pong.wait()).await {
+            Either::First(reply) => {
+                debug!("WS TX: {:?}", reply);
+                // One JSON object per message, without the line terminator.
+                encode_frame(OPCODE_TEXT, tx_frame, |buf| {
+                    encode_reply(&reply, buf).map(|len| len - 1)
+                })
+            }
+            Either::Second(payload) => encode_frame(OPCODE_PONG, tx_frame, |buf| {
+                buf.get_mut(..payload.len())?.copy_from_slice(&payload);
+                Some(payload.len())
+            }),
+        };
+        let Some(len) = encoded else {
+            error!("WebSocket encode error");
+            continue;
+        };
+        if let Err(e) = writer.write_all(&tx_frame[..len]).await {
+            error!("WebSocket write error: {:?}", e);
+            return;
+        }
+    }
+}